@@ -1,133 +1,152 @@
-extern crate itertools;
 extern crate rand;
+#[cfg(feature = "tcod")]
 extern crate tcod;
 extern crate world;
 
-use itertools::Itertools;
-use rand::StdRng;
-use std::collections::HashSet;
-use tcod::input::Key::{Special, Printable};
-use tcod::input::KeyCode::{Up, Down, Left, Right, Escape};
-use tcod::{Console, BackgroundFlag, RootInitializer};
-use world::{WorldMap, Terrain, Location, Dijkstra};
-
-fn explore(loc: Location, radius: i32, explored: &mut HashSet<Location>,
-    unexplored: &mut HashSet<Location>) {
-    let center = Location::new(0, 0);
-    for i in -radius..radius+1 {
-        for j in -radius..radius+1 {
-            let circle_loc = Location::new(i, j);
-            let dist = circle_loc.euclidean(&center);
-            if dist <= radius {
-                let explored_loc =
-                    Location::new(loc.x + circle_loc.x, loc.y + circle_loc.y);
-                explored.insert(explored_loc);
-                unexplored.remove(&explored_loc);
-            }
-        }
-    }
+#[cfg(feature = "tcod")]
+mod tcod_backend;
+
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use world::{GenerationConfig, SavedGame, Vault, Game, Backend, Input,
+    Recorder, Replay, new_game, save_game, load_game, delete_save};
+
+#[cfg(not(any(feature = "tcod", feature = "terminal")))]
+compile_error!("Enable the \"tcod\" or \"terminal\" feature to have something \
+    to play the game in.");
+
+// Size of the window in cells.
+#[cfg(feature = "tcod")]
+const SCREEN_WIDTH: i32 = 80;
+#[cfg(feature = "tcod")]
+const SCREEN_HEIGHT: i32 = 50;
+// Where the game is saved on quitting. It's picked up again, and deleted,
+// the next time the game starts.
+const SAVE_FILE: &'static str = "roguelike.sav";
+// Where every key pressed is recorded, so the game can be replayed.
+const REPLAY_FILE: &'static str = "roguelike.replay";
+
+const USAGE: &'static str = "Usage: roguelike [--seed <n>] [--terminal] \
+    [--replay <file> [--delay <ms>]]";
+
+// Report something the player needs to fix and quit.
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+// Parse an option like "--seed <n>" from the command line, if present.
+fn parse_arg<T: FromStr>(args: &[String], name: &str) -> Option<T> {
+    args.iter()
+        .position(|a| a == name)
+        .map(|i| match args.get(i + 1) {
+            Some(s) => s.parse::<T>().unwrap_or_else(|_| exit_with_error(
+                &format!("Invalid value for {}: {}\n{}", name, s, USAGE))),
+            None => exit_with_error(
+                &format!("Missing value for {}\n{}", name, USAGE))
+        })
 }
 
-fn draw_world(console: &mut tcod::console::Root, world: &WorldMap,
-    explored: &HashSet<Location>, player_loc: Location) {
-    //console.clear();
-    for tile in world.tiles() {
-        let ascii = match (tile.terrain, explored.contains(&tile.loc)) {
-            (Terrain::Floor, true) => '.',
-            (Terrain::Wall, true) => '#',
-            (Terrain::Debug, true) => 'X',
-            _ => ' '
-        };
-        console.put_char(tile.loc.x, tile.loc.y, ascii,
-            BackgroundFlag::Set);
+fn config(seed: usize, vaults: Vec<Vault>) -> GenerationConfig {
+    GenerationConfig {
+        width: 300,
+        height: 300,
+        seed: seed,
+        vaults: vaults,
+        ..Default::default()
     }
+}
 
-    console.put_char(player_loc.x, player_loc.y, '@', BackgroundFlag::Set);
-    console.flush();
+fn play<B: Backend>(backend: &mut B, saved: SavedGame, input: &mut Input)
+    -> Game {
+    let mut game = Game::new(saved, backend.size());
+    game.run(backend, input);
+    game
+}
+
+#[cfg(feature = "tcod")]
+fn play_in_window(saved: SavedGame, input: &mut Input) -> Game {
+    let mut backend = tcod_backend::TcodBackend::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    play(&mut backend, saved, input)
+}
+
+#[cfg(not(feature = "tcod"))]
+fn play_in_window(_: SavedGame, _: &mut Input) -> Game {
+    unreachable!()
+}
+
+#[cfg(feature = "terminal")]
+fn play_in_terminal(saved: SavedGame, input: &mut Input) -> Game {
+    let mut backend = world::TerminalBackend::open()
+        .unwrap_or_else(|e| panic!("Failed to set up the terminal: {}", e));
+    play(&mut backend, saved, input)
+}
+
+#[cfg(not(feature = "terminal"))]
+fn play_in_terminal(_: SavedGame, _: &mut Input) -> Game {
+    panic!("Built without terminal support. Rebuild with --features terminal.")
 }
 
 fn main() {
-    let width = 150; //80;
-    let height = 100; //50;
-    let player_radius = 5;
-    let mut console = RootInitializer::new()
-        .size(width, height)
-        .title("Roguelike")
-        .init();
-    let mut rng = StdRng::new().unwrap();
-    let (world, starting_loc) = WorldMap::generate(&mut rng, width, height);
-    let mut explored = HashSet::new();
-    let mut unexplored: HashSet<Location> = world.tiles()
-        .filter(|t| t.terrain == Terrain::Floor || t.terrain == Terrain::Wall)
-        .map(|t| t.loc)
-        .collect();
-    let mut player_loc = starting_loc;
-    explore(player_loc, player_radius, &mut explored, &mut unexplored);
-
-    while !console.window_closed() {
-        // Draw world.
-        draw_world(&mut console, &world, &explored, player_loc);
-
-        // Check for keypress.
-        let keypress = console.wait_for_keypress(true);
-        if keypress.pressed {
-            let new_loc = match keypress.key {
-                Special(Escape) => break,
-                Special(Up) => {
-                    if player_loc.y > 0 {
-                        Location::new(player_loc.x, player_loc.y - 1)
-                    } else { player_loc }
-                },
-                Special(Down) => Location::new(player_loc.x, player_loc.y + 1),
-                Special(Left) => {
-                    if player_loc.x > 0 {
-                        Location::new(player_loc.x - 1, player_loc.y)
-                    } else { player_loc }
-                },
-                Special(Right) => Location::new(player_loc.x + 1, player_loc.y),
-                Printable('y') => {
-                    if player_loc.x > 0 && player_loc.y > 0 {
-                        Location::new(player_loc.x - 1, player_loc.y - 1)
-                    } else { player_loc }
-                },
-                Printable('u') => {
-                    if player_loc.y > 0 {
-                        Location::new(player_loc.x + 1, player_loc.y - 1)
-                    } else { player_loc }
-                },
-                Printable('b') => {
-                    if player_loc.x > 0 {
-                        Location::new(player_loc.x - 1, player_loc.y + 1)
-                    } else { player_loc }
-                },
-                Printable('n') => {
-                    Location::new(player_loc.x + 1, player_loc.y + 1)
-                },
-                Printable('o') => {
-                    while !unexplored.is_empty() {
-                        let goals: Vec<Location> = unexplored.iter().map(|l| *l).collect();
-                        let dmap = Dijkstra::new(&world, goals, 1000);
-                        let possible: Vec<Location> = dmap
-                            .sorted_neighbors(&player_loc).iter()
-                            .filter(|l| world.get_tile(**l).terrain == Terrain::Floor)
-                            .map(|l| *l)
-                            .collect();
-                        player_loc = possible[0];
-                        explore(player_loc, player_radius, &mut explored, &mut unexplored);
-                        draw_world(&mut console, &world, &explored, player_loc);
-                    }
-                    player_loc
-                }
-                _ => player_loc
-            };
-            match world.get_tile(new_loc).terrain {
-                Terrain::Floor | Terrain::Debug => {
-                    player_loc = new_loc;
-                    explore(player_loc, player_radius, &mut explored, &mut unexplored);
-                },
-                Terrain::Wall => {},
-                Terrain::Nothing => {}
-            }
+    let args: Vec<String> = std::env::args().collect();
+    let vault_dir = Path::new("vaults");
+    let vaults = if vault_dir.is_dir() {
+        Vault::load_dir(vault_dir).unwrap_or_else(|e| exit_with_error(
+            &format!("Failed to load vaults: {}", e)))
+    } else { Vec::new() };
+    let replay = parse_arg::<String>(&args, "--replay").map(|file|
+        Replay::load(Path::new(&file)).unwrap_or_else(|e| exit_with_error(
+            &format!("Failed to load replay {}: {}", file, e))));
+    // Replays leave the saved game alone.
+    let save_path = match replay {
+        Some(_) => None,
+        None => Some(Path::new(SAVE_FILE))
+    };
+    let replay_path = Path::new(REPLAY_FILE);
+    let (saved, mut input) = match replay {
+        Some(replay) => {
+            let delay = parse_arg(&args, "--delay").unwrap_or(50);
+            (new_game(config(replay.seed, vaults)),
+                Input::from_replay(replay, Duration::from_millis(delay)))
+        },
+        None if Path::new(SAVE_FILE).exists() => {
+            let mut saved = load_game(Path::new(SAVE_FILE), vaults)
+                .unwrap_or_else(|e| exit_with_error(&format!("Failed to \
+                    load {}: {}. Delete it to start a new game.",
+                    SAVE_FILE, e)));
+            saved.log.add("Welcome back!", saved.scheduler.time());
+            let recorder = Recorder::append(replay_path)
+                .map_err(|e| eprintln!("Not recording a replay: {}", e));
+            (saved, Input::new(recorder.ok()))
+        },
+        None => {
+            let seed = parse_arg(&args, "--seed")
+                .unwrap_or_else(|| rand::random::<usize>());
+            println!("Seed: {}", seed);
+            let recorder = Recorder::create(replay_path, seed)
+                .map_err(|e| eprintln!("Not recording a replay: {}", e));
+            (new_game(config(seed, vaults)), Input::new(recorder.ok()))
+        }
+    };
+
+    // Without libtcod the game can only be played in the terminal.
+    let game = if !cfg!(feature = "tcod") || args.iter().any(|a| a == "--terminal") {
+        play_in_terminal(saved, &mut input)
+    } else {
+        play_in_window(saved, &mut input)
+    };
+
+    // Quitting saves the game, unless it's over.
+    if let Some(path) = save_path {
+        if game.is_over() {
+            delete_save(path).unwrap_or_else(|e| exit_with_error(
+                &format!("Failed to delete save: {}", e)));
+        } else {
+            save_game(path, &game.into_saved()).unwrap_or_else(|e|
+                exit_with_error(&format!("Failed to save game: {}", e)));
+            input.record_save();
         }
     }
 }
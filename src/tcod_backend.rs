@@ -0,0 +1,72 @@
+use tcod;
+use tcod::input::KeyCode;
+use tcod::input::KEY_PRESSED;
+use tcod::{Console, RootInitializer};
+use world::{Backend, Key, Color};
+
+/// Draws the game in a libtcod window.
+pub struct TcodBackend {
+    root: tcod::console::Root
+}
+
+impl TcodBackend {
+    pub fn new(width: i32, height: i32) -> Self {
+        let root = RootInitializer::new()
+            .size(width, height)
+            .title("Roguelike")
+            .init();
+        TcodBackend {root: root}
+    }
+}
+
+// Translate a key from libtcod, if it's one the game knows about.
+fn to_key(key: tcod::input::Key) -> Option<Key> {
+    match key {
+        tcod::input::Key::Printable(c) => Some(Key::Char(c)),
+        tcod::input::Key::Special(code) => match code {
+            KeyCode::Up => Some(Key::Up),
+            KeyCode::Down => Some(Key::Down),
+            KeyCode::Left => Some(Key::Left),
+            KeyCode::Right => Some(Key::Right),
+            KeyCode::Escape => Some(Key::Escape),
+            KeyCode::Enter => Some(Key::Enter),
+            KeyCode::Backspace => Some(Key::Backspace),
+            KeyCode::Tab => Some(Key::Tab),
+            KeyCode::Spacebar => Some(Key::Char(' ')),
+            KeyCode::PageUp => Some(Key::PageUp),
+            KeyCode::PageDown => Some(Key::PageDown),
+            KeyCode::Home => Some(Key::Home),
+            KeyCode::End => Some(Key::End),
+            _ => None
+        }
+    }
+}
+
+fn to_color((r, g, b): Color) -> tcod::Color {
+    tcod::Color::new(r, g, b)
+}
+
+impl Backend for TcodBackend {
+    fn size(&self) -> (i32, i32) {
+        (self.root.width(), self.root.height())
+    }
+    fn put(&mut self, x: i32, y: i32, glyph: char, fg: Color, bg: Color) {
+        self.root.put_char_ex(x, y, glyph, to_color(fg), to_color(bg));
+    }
+    fn flush(&mut self) {
+        self.root.flush();
+    }
+    fn wait_key(&mut self) -> Option<Key> {
+        while !self.root.window_closed() {
+            let keypress = self.root.wait_for_keypress(true);
+            if let (true, Some(key)) = (keypress.pressed, to_key(keypress.key)) {
+                return Some(key);
+            }
+        }
+        None
+    }
+    fn poll_key(&mut self) -> Option<Key> {
+        self.root.check_for_keypress(KEY_PRESSED)
+            .and_then(|keypress| to_key(keypress.key))
+    }
+}
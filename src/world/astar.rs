@@ -0,0 +1,201 @@
+use cost::MoveCost;
+use tile::Location;
+use world_map::WorldMap;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// An entry in the A* open set. Ordered so that the BinaryHeap (a max-heap)
+// pops the entry with the lowest estimated total cost first.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct OpenNode {
+    estimate: i32,
+    cost: i32,
+    loc: Location
+}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+            // Among equal estimates, prefer nodes closer to the goal.
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| (other.loc.y, other.loc.x)
+                .cmp(&(self.loc.y, self.loc.x)))
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl WorldMap {
+    /// Find the cheapest path between two locations with A* search. Steps
+    /// go to adjacent cells and cost what the move cost says they do. The
+    /// heuristic estimates the cost left between two locations, e.g.
+    /// Location::chebyshev for 8-way movement or Location::manhattan for
+    /// 4-way movement, scaled by the cheapest step; the path is cheapest as
+    /// long as it never overestimates.
+    ///
+    /// The returned path leads from the cell after from up to and including
+    /// to, so it's empty if they're the same. Returns None if there's no
+    /// path.
+    pub fn find_path<H>(&self, from: Location, to: Location, heuristic: H,
+        cost: &MoveCost) -> Option<Vec<Location>>
+        where H: Fn(&Location, &Location) -> i32
+    {
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<Location, Location> = HashMap::new();
+        let mut costs: HashMap<Location, i32> = HashMap::new();
+        open.push(OpenNode {
+            estimate: heuristic(&from, &to),
+            cost: 0,
+            loc: from
+        });
+        costs.insert(from, 0);
+
+        while let Some(node) = open.pop() {
+            if node.loc == to {
+                // Walk back to the start to recover the path.
+                let mut path = vec![to];
+                let mut loc = to;
+                while let Some(prev) = came_from.get(&loc) {
+                    if *prev == from {
+                        break;
+                    }
+                    path.push(*prev);
+                    loc = *prev;
+                }
+                if from == to {
+                    path.clear();
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if node.cost > costs[&node.loc] {
+                // A cheaper way here was already found.
+                continue;
+            }
+
+            for adj in self.get_adjacent(node.loc, cost.allows_diagonal()) {
+                let cost = match cost.step(self, node.loc, adj) {
+                    Some(c) => node.cost + c as i32,
+                    None => continue
+                };
+                if costs.get(&adj).map_or(true, |c| cost < *c) {
+                    costs.insert(adj, cost);
+                    came_from.insert(adj, node.loc);
+                    open.push(OpenNode {
+                        estimate: cost + heuristic(&adj, &to),
+                        cost: cost,
+                        loc: adj
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn test_find_path() {
+    use config::GenerationConfig;
+    use tile::Terrain;
+    use dijkstra::Dijkstra;
+
+    let config = GenerationConfig {
+        width: 80,
+        height: 50,
+        seed: 9,
+        ..Default::default()
+    };
+    let (world, start) = WorldMap::generate(&config);
+    let floors: Vec<Location> = world.tiles()
+        .filter(|t| t.terrain.is_passable())
+        .map(|t| t.loc)
+        .collect();
+    let dmap = Dijkstra::new(&world, vec![start], 10000);
+    let diag = MoveCost::uniform();
+    let orthogonal = MoveCost::uniform().diagonal(None);
+
+    assert_eq!(world.find_path(start, start, Location::chebyshev, &diag),
+        Some(Vec::new()));
+    for goal in floors.iter().step_by(37) {
+        // With an admissible heuristic, paths are as short as the Dijkstra
+        // map says they should be.
+        let path = world.find_path(start, *goal, Location::chebyshev, &diag)
+            .unwrap();
+        assert_eq!(path.len() as i32, dmap.get(goal).unwrap());
+        assert_eq!(path.last(), Some(goal));
+        let mut prev = start;
+        for step in path.iter() {
+            assert_eq!(prev.chebyshev(step), 1);
+            assert!(world.get_tile(*step).terrain.is_passable());
+            prev = *step;
+        }
+
+        // 4-way paths never cut corners.
+        let path = world.find_path(start, *goal, Location::manhattan, &orthogonal)
+            .unwrap();
+        let mut prev = start;
+        for step in path.iter() {
+            assert_eq!(prev.manhattan(step), 1);
+            prev = *step;
+        }
+    }
+
+    // Walls can't be reached.
+    let wall = world.tiles().find(|t| t.terrain == Terrain::Wall).unwrap().loc;
+    assert_eq!(world.find_path(start, wall, Location::chebyshev, &diag), None);
+}
+
+#[test]
+fn test_find_path_with_terrain_cost() {
+    use config::GenerationConfig;
+    use dijkstra::Dijkstra;
+    use tile::Terrain;
+
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        seed: 2,
+        ..Default::default()
+    };
+    let (mut world, start) = WorldMap::generate(&config);
+    // Flood every third column with water.
+    let flooded: Vec<Location> = world.tiles()
+        .filter(|t| t.terrain == Terrain::Floor && t.loc.x % 3 == 0 &&
+            t.loc != start)
+        .map(|t| t.loc)
+        .collect();
+    for loc in flooded.iter() {
+        world.get_tile_mut(*loc).terrain = Terrain::ShallowWater;
+    }
+
+    let cost = MoveCost::terrain().diagonal(Some(1));
+    let dmap = Dijkstra::with_cost(&world, vec![start], 10000, cost.clone());
+    let floors: Vec<Location> = world.tiles()
+        .filter(|t| t.terrain == Terrain::Floor)
+        .map(|t| t.loc)
+        .collect();
+    let path_cost = |path: &Vec<Location>| {
+        let mut prev = start;
+        let mut total = 0;
+        for step in path.iter() {
+            total += cost.step(&world, prev, *step).unwrap();
+            prev = *step;
+        }
+        total as i32
+    };
+    for goal in floors.iter().step_by(23) {
+        // Paths cost as much as the Dijkstra map says they should, and are
+        // never worse than paths that ignore the terrain.
+        let path = world.find_path(start, *goal, Location::chebyshev, &cost)
+            .unwrap();
+        assert_eq!(path_cost(&path), dmap.get(goal).unwrap());
+        let uniform = world.find_path(start, *goal, Location::chebyshev,
+            &MoveCost::uniform()).unwrap();
+        assert!(path_cost(&path) <= path_cost(&uniform));
+    }
+}
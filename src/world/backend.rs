@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+
+/// A color as red, green and blue.
+pub type Color = (u8, u8, u8);
+
+pub const WHITE: Color = (255, 255, 255);
+pub const BLACK: Color = (0, 0, 0);
+
+/// A key the game knows about.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Key {
+    // A key that types a character, including space.
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Escape,
+    Enter,
+    Backspace,
+    Tab,
+    PageUp,
+    PageDown,
+    Home,
+    End
+}
+
+/// Something the game can be drawn on and read keys from, like a window or
+/// a terminal. Nothing drawn shows up until the backend is flushed.
+pub trait Backend {
+    /// Return the width and height of the screen in cells.
+    fn size(&self) -> (i32, i32);
+    fn put(&mut self, x: i32, y: i32, glyph: char, fg: Color, bg: Color);
+    fn flush(&mut self);
+    /// Wait for a key to be pressed. Returns None once there won't be any
+    /// more keys, e.g. because the window was closed.
+    fn wait_key(&mut self) -> Option<Key>;
+    /// Return a key if one has been pressed, without waiting.
+    fn poll_key(&mut self) -> Option<Key>;
+
+    /// Write some text in white on black, cut off at the edge of the
+    /// screen.
+    fn print(&mut self, x: i32, y: i32, text: &str) {
+        let width = self.size().0;
+        for (i, c) in text.chars().enumerate() {
+            if x + i as i32 >= width {
+                break;
+            }
+            self.put(x + i as i32, y, c, WHITE, BLACK);
+        }
+    }
+    fn clear_area(&mut self, x: i32, y: i32, width: i32, height: i32) {
+        for j in y..y + height {
+            for i in x..x + width {
+                self.put(i, j, ' ', WHITE, BLACK);
+            }
+        }
+    }
+}
+
+/// A backend that only exists in memory, for running the game without a
+/// display. Keys come from a script given up front, and the screen can be
+/// read back as text.
+pub struct HeadlessBackend {
+    width: i32,
+    height: i32,
+    cells: Vec<(char, Color, Color)>,
+    // What the screen looked like when it was last flushed.
+    screen: Vec<(char, Color, Color)>,
+    keys: VecDeque<Key>,
+    flushes: usize
+}
+
+impl HeadlessBackend {
+    pub fn new(width: i32, height: i32, keys: Vec<Key>) -> Self {
+        let blank = vec![(' ', WHITE, BLACK); (width * height) as usize];
+        HeadlessBackend {
+            width: width,
+            height: height,
+            cells: blank.clone(),
+            screen: blank,
+            keys: keys.into_iter().collect(),
+            flushes: 0
+        }
+    }
+    /// Return a line of the screen as last flushed.
+    pub fn row(&self, y: i32) -> String {
+        let start = (y * self.width) as usize;
+        self.screen[start..start + self.width as usize].iter()
+            .map(|&(c, _, _)| c)
+            .collect()
+    }
+    /// Return a cell of the screen as last flushed.
+    pub fn cell(&self, x: i32, y: i32) -> (char, Color, Color) {
+        self.screen[(y * self.width + x) as usize]
+    }
+    /// Return the whole screen as last flushed, one line per row.
+    pub fn text(&self) -> String {
+        (0..self.height).map(|y| self.row(y)).collect::<Vec<_>>().join("\n")
+    }
+    /// Return how many times the screen has been flushed.
+    pub fn flushes(&self) -> usize {
+        self.flushes
+    }
+    pub fn push_key(&mut self, key: Key) {
+        self.keys.push_back(key);
+    }
+}
+
+impl Backend for HeadlessBackend {
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+    fn put(&mut self, x: i32, y: i32, glyph: char, fg: Color, bg: Color) {
+        // Drawing off the screen does nothing, like it would on a console.
+        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+            self.cells[(y * self.width + x) as usize] = (glyph, fg, bg);
+        }
+    }
+    fn flush(&mut self) {
+        self.screen.clone_from(&self.cells);
+        self.flushes += 1;
+    }
+    fn wait_key(&mut self) -> Option<Key> {
+        self.keys.pop_front()
+    }
+    fn poll_key(&mut self) -> Option<Key> {
+        self.keys.pop_front()
+    }
+}
+
+#[test]
+fn test_headless_backend() {
+    let mut backend = HeadlessBackend::new(10, 2, vec![Key::Char('a')]);
+    backend.print(2, 1, "hello there");
+    assert_eq!(backend.row(1), "          ");
+    backend.flush();
+    assert_eq!(backend.text(), "          \n  hello th");
+    backend.put(0, 0, '@', (255, 0, 0), BLACK);
+    backend.put(-1, 5, '@', WHITE, BLACK);
+    backend.flush();
+    assert_eq!(backend.cell(0, 0), ('@', (255, 0, 0), BLACK));
+    assert_eq!(backend.flushes(), 2);
+    assert_eq!(backend.wait_key(), Some(Key::Char('a')));
+    assert_eq!(backend.wait_key(), None);
+}
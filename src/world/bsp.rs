@@ -0,0 +1,108 @@
+extern crate rand;
+
+use tile::Location;
+use self::rand::Rng;
+
+/// An axis-aligned rectangle of map cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Self {
+        Rect {x: x, y: y, width: width, height: height}
+    }
+    pub fn contains(&self, loc: &Location) -> bool {
+        loc.x >= self.x && loc.x < self.x + self.width &&
+            loc.y >= self.y && loc.y < self.y + self.height
+    }
+    pub fn area(&self) -> i32 {
+        self.width * self.height
+    }
+}
+
+/// A binary space partition of a rectangle. Each split divides its
+/// rectangle in two along a random line; the leaves are the rectangles
+/// left over once nothing can be split any further.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BspTree {
+    Leaf(Rect),
+    Split(Box<BspTree>, Box<BspTree>)
+}
+
+impl BspTree {
+    /// Recursively split rect until no leaf can be split without one of
+    /// its halves being narrower or shorter than min_size.
+    pub fn split<R: Rng>(rng: &mut R, rect: Rect, min_size: i32) -> Self {
+        assert!(min_size > 0);
+
+        let can_split_x = rect.width >= 2 * min_size;
+        let can_split_y = rect.height >= 2 * min_size;
+        let split_x = match (can_split_x, can_split_y) {
+            (false, false) => return BspTree::Leaf(rect),
+            (true, false) => true,
+            (false, true) => false,
+            // Prefer cutting across the longer side to avoid long, thin
+            // leaves.
+            (true, true) => {
+                if rect.width > rect.height * 5 / 4 { true }
+                else if rect.height > rect.width * 5 / 4 { false }
+                else { rng.gen::<bool>() }
+            }
+        };
+
+        let (first, second) = if split_x {
+            let w = rng.gen_range::<i32>(min_size, rect.width - min_size + 1);
+            (Rect::new(rect.x, rect.y, w, rect.height),
+                Rect::new(rect.x + w, rect.y, rect.width - w, rect.height))
+        } else {
+            let h = rng.gen_range::<i32>(min_size, rect.height - min_size + 1);
+            (Rect::new(rect.x, rect.y, rect.width, h),
+                Rect::new(rect.x, rect.y + h, rect.width, rect.height - h))
+        };
+
+        BspTree::Split(
+            Box::new(BspTree::split(rng, first, min_size)),
+            Box::new(BspTree::split(rng, second, min_size)))
+    }
+    pub fn leaves(&self) -> Vec<Rect> {
+        match *self {
+            BspTree::Leaf(rect) => vec![rect],
+            BspTree::Split(ref a, ref b) => {
+                let mut leaves = a.leaves();
+                leaves.extend(b.leaves());
+                leaves
+            }
+        }
+    }
+}
+
+#[test]
+fn test_bsp_leaves_partition_rect() {
+    use self::rand::{StdRng, SeedableRng};
+
+    let mut rng = StdRng::from_seed(&[7][..]);
+    let rect = Rect::new(1, 1, 78, 48);
+    let min_size = 8;
+    let leaves = BspTree::split(&mut rng, rect, min_size).leaves();
+    assert!(leaves.len() > 1);
+
+    // Every leaf is large enough and none could be split further.
+    for leaf in leaves.iter() {
+        assert!(leaf.width >= min_size && leaf.height >= min_size);
+        assert!(leaf.width < 2 * min_size && leaf.height < 2 * min_size);
+    }
+
+    // Every cell of the rect lies in exactly one leaf.
+    assert_eq!(leaves.iter().map(|l| l.area()).sum::<i32>(), rect.area());
+    for x in rect.x..rect.x + rect.width {
+        for y in rect.y..rect.y + rect.height {
+            let loc = Location::new(x, y);
+            assert_eq!(leaves.iter().filter(|l| l.contains(&loc)).count(), 1);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use tile::Location;
+use std::cmp;
+
+/// Maps world locations to cells of a view that may be smaller than the
+/// map. The camera keeps its target inside the deadzone, a box of the
+/// given size in the middle of the view, and only scrolls once the target
+/// leaves it. It never scrolls past the edges of the map.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Camera {
+    // World location shown in the top left cell of the view.
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub deadzone: (i32, i32)
+}
+
+impl Camera {
+    pub fn new(width: i32, height: i32, deadzone: (i32, i32)) -> Self {
+        Camera {
+            x: 0,
+            y: 0,
+            width: width,
+            height: height,
+            deadzone: (cmp::min(deadzone.0, width), cmp::min(deadzone.1, height))
+        }
+    }
+    /// Scroll just far enough to bring the target back into the deadzone,
+    /// keeping the view within a map of the given size.
+    pub fn follow(&mut self, target: Location, map_width: i32,
+        map_height: i32) {
+        self.x = scroll(self.x, target.x, self.width, self.deadzone.0,
+            map_width);
+        self.y = scroll(self.y, target.y, self.height, self.deadzone.1,
+            map_height);
+    }
+    /// Scroll so the target is as close to the middle of the view as the
+    /// map allows, e.g. after changing levels.
+    pub fn center_on(&mut self, target: Location, map_width: i32,
+        map_height: i32) {
+        self.x = clamp(target.x - self.width / 2, self.width, map_width);
+        self.y = clamp(target.y - self.height / 2, self.height, map_height);
+    }
+    /// Return the cell of the view showing a location, if it's in view.
+    pub fn to_screen(&self, loc: Location) -> Option<(i32, i32)> {
+        let (x, y) = (loc.x - self.x, loc.y - self.y);
+        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+    /// Return the location shown in a cell of the view.
+    pub fn to_world(&self, x: i32, y: i32) -> Location {
+        Location::new(self.x + x, self.y + y)
+    }
+}
+
+// Work out the new offset along one axis.
+fn scroll(offset: i32, target: i32, size: i32, deadzone: i32,
+    map_size: i32) -> i32 {
+    let low = offset + (size - deadzone) / 2;
+    let high = low + deadzone - 1;
+    let offset = if target < low {
+        offset - (low - target)
+    } else if target > high {
+        offset + (target - high)
+    } else {
+        offset
+    };
+
+    clamp(offset, size, map_size)
+}
+
+// Keep a view of the given size within the map. Maps smaller than the view
+// stay in the top left corner.
+fn clamp(offset: i32, size: i32, map_size: i32) -> i32 {
+    cmp::max(0, cmp::min(offset, map_size - size))
+}
+
+#[test]
+fn test_camera() {
+    let mut camera = Camera::new(20, 10, (6, 4));
+    camera.center_on(Location::new(50, 50), 100, 100);
+    assert_eq!((camera.x, camera.y), (40, 45));
+    assert_eq!(camera.to_screen(Location::new(50, 50)), Some((10, 5)));
+    assert_eq!(camera.to_world(10, 5), Location::new(50, 50));
+
+    // Moving around inside the deadzone doesn't scroll.
+    camera.follow(Location::new(52, 51), 100, 100);
+    assert_eq!((camera.x, camera.y), (40, 45));
+
+    // Leaving it scrolls just enough to keep the target on its edge.
+    camera.follow(Location::new(55, 48), 100, 100);
+    assert_eq!((camera.x, camera.y), (43, 45));
+    camera.follow(Location::new(55, 60), 100, 100);
+    assert_eq!((camera.x, camera.y), (43, 54));
+    assert_eq!(camera.to_screen(Location::new(55, 60)), Some((12, 6)));
+    assert_eq!(camera.to_screen(Location::new(42, 60)), None);
+
+    // The view stops at the edges of the map.
+    camera.follow(Location::new(99, 99), 100, 100);
+    assert_eq!((camera.x, camera.y), (80, 90));
+    camera.center_on(Location::new(1, 1), 100, 100);
+    assert_eq!((camera.x, camera.y), (0, 0));
+    camera.center_on(Location::new(5, 5), 8, 8);
+    assert_eq!((camera.x, camera.y), (0, 0));
+}
@@ -0,0 +1,148 @@
+extern crate rand;
+
+use ecs::{Ecs, Entity};
+use std::cmp;
+use self::rand::Rng;
+
+/// What happened when one entity attacked another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttackResult {
+    pub attacker: Entity,
+    pub defender: Entity,
+    // Names are kept since the defender may not be around any more.
+    pub attacker_name: String,
+    pub defender_name: String,
+    // Damage dealt once the defender's defense is taken off. Zero means the
+    // blow didn't get through.
+    pub damage: i32,
+    pub killed: bool
+}
+
+/// Have one entity hit another in melee. The attacker rolls between 1 and
+/// its attack stat, the defender's defense is subtracted from the roll and
+/// whatever is left comes off the defender's health. Entities without
+/// combat stats count as having 0 attack and defense.
+pub fn attack<R: Rng>(rng: &mut R, ecs: &mut Ecs, attacker: Entity,
+    defender: Entity) -> AttackResult
+{
+    let attack = ecs.combat.get(attacker).map_or(0, |c| c.attack);
+    let defense = ecs.combat.get(defender).map_or(0, |c| c.defense);
+    let roll = if attack > 0 {
+        rng.gen_range::<i32>(1, attack + 1)
+    } else { 0 };
+    let damage = cmp::max(0, roll - defense);
+    let name = |e| ecs.names.get(e).cloned()
+        .unwrap_or("something".to_string());
+    let (attacker_name, defender_name) = (name(attacker), name(defender));
+
+    let killed = match ecs.healths.get_mut(defender) {
+        Some(health) if health.current > 0 => {
+            health.current = cmp::max(0, health.current - damage);
+            health.current == 0
+        },
+        _ => false
+    };
+    if killed {
+        die(ecs, defender);
+    }
+
+    AttackResult {
+        attacker: attacker,
+        defender: defender,
+        attacker_name: attacker_name,
+        defender_name: defender_name,
+        damage: damage,
+        killed: killed
+    }
+}
+
+impl AttackResult {
+    /// Describe the attack for the message log, e.g. "You hit the rat." or
+    /// "The rat misses you." The player is the entity named "you".
+    pub fn describe(&self) -> String {
+        let verb = if self.killed {
+            "kill"
+        } else if self.damage > 0 {
+            "hit"
+        } else {
+            "miss"
+        };
+        let (subject, verb) = if self.attacker_name == "you" {
+            ("You".to_string(), verb.to_string())
+        } else if verb == "miss" {
+            (format!("The {}", self.attacker_name), "misses".to_string())
+        } else {
+            (format!("The {}", self.attacker_name), format!("{}s", verb))
+        };
+        let object = if self.defender_name == "you" {
+            "you".to_string()
+        } else {
+            format!("the {}", self.defender_name)
+        };
+
+        format!("{} {} {}.", subject, verb, object)
+    }
+}
+
+/// Whether an entity has run out of health.
+pub fn is_dead(ecs: &Ecs, entity: Entity) -> bool {
+    ecs.healths.get(entity).map_or(false, |h| h.current <= 0)
+}
+
+// Monsters that die are removed from the level. Anything else, like the
+// player, is left where it is with no health for the caller to deal with.
+fn die(ecs: &mut Ecs, entity: Entity) {
+    if ecs.monsters.contains(entity) {
+        ecs.destroy(entity);
+    }
+}
+
+#[test]
+fn test_attack() {
+    use ecs::{CombatStats, Health};
+    use monster::{spawn_monster, GOBLIN};
+    use tile::Location;
+    use self::rand::{StdRng, SeedableRng};
+
+    let mut rng = StdRng::from_seed(&[5][..]);
+    let mut ecs = Ecs::new();
+    let player = ecs.create();
+    ecs.healths.insert(player, Health { current: 10, max: 10 });
+    ecs.combat.insert(player, CombatStats { attack: 1, defense: 0 });
+    let goblin = spawn_monster(&mut ecs, &GOBLIN, Location::new(1, 1));
+
+    // A weak attack never gets through the goblin's defense.
+    ecs.names.insert(player, "you".to_string());
+    for _ in 0..20 {
+        let result = attack(&mut rng, &mut ecs, player, goblin);
+        assert_eq!(result.damage, 0);
+        assert_eq!(result.describe(), "You miss the goblin.");
+    }
+
+    // A strong one kills it, taking it off the level.
+    ecs.combat.insert(player, CombatStats { attack: 20, defense: 0 });
+    let mut dealt = 0;
+    loop {
+        let result = attack(&mut rng, &mut ecs, player, goblin);
+        assert!(result.damage > 0);
+        dealt += result.damage;
+        if result.killed {
+            assert_eq!(result.describe(), "You kill the goblin.");
+            break;
+        }
+    }
+    assert!(dealt >= GOBLIN.health);
+    assert!(!ecs.is_alive(goblin));
+    assert!(ecs.entities_at(Location::new(1, 1)).is_empty());
+
+    // The player stays around when killed.
+    ecs.healths.insert(player, Health { current: 1, max: 10 });
+    let orc = ecs.create();
+    ecs.names.insert(orc, "orc".to_string());
+    ecs.combat.insert(orc, CombatStats { attack: 5, defense: 0 });
+    let result = attack(&mut rng, &mut ecs, orc, player);
+    assert_eq!(result.describe(), "The orc kills you.");
+    assert!(ecs.is_alive(player));
+    assert!(is_dead(&ecs, player));
+    assert_eq!(ecs.healths.get(player).unwrap().current, 0);
+}
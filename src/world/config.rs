@@ -0,0 +1,227 @@
+extern crate rand;
+
+use vault::Vault;
+use save::{Save, Load, SaveWriter, SaveReader, SaveError, corrupt};
+use self::rand::{StdRng, SeedableRng};
+
+/// The algorithm used to lay out a map.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GenerationAlgorithm {
+    // Grow rooms off random walls, each connected by a hallway.
+    Rooms,
+    // Recursively split the map and place a room in each leaf, connecting
+    // sibling leaves with corridors.
+    Bsp,
+    // Smooth random noise into caverns with a cellular automaton.
+    Caves
+}
+
+/// Parameters controlling map generation. Two maps generated from configs
+/// that compare equal are identical, so a config (in particular its seed) is
+/// all that is needed to reproduce a map.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenerationConfig {
+    pub width: i32,
+    pub height: i32,
+    pub algorithm: GenerationAlgorithm,
+    // Depth of the level in the dungeon, starting at 1.
+    pub depth: u32,
+    // Seed for the random number generator returned by rng().
+    pub seed: usize,
+    // Number of times to try attaching a new feature to a random wall.
+    pub attempts: u32,
+    // Relative weights for picking each kind of feature.
+    pub room_weight: u32,
+    pub diamond_room_weight: u32,
+    pub circle_room_weight: u32,
+    // Prefab vaults to pick from along with the rooms above. Only vaults
+    // allowed at the config's depth are used.
+    pub vaults: Vec<Vault>,
+    // Ranges are half-open, as in Rng::gen_range.
+    pub room_size: (i32, i32),
+    pub room_radius: (i32, i32),
+    pub hallway_length: (i32, i32),
+    // Percentage of hallways that get a door where they meet a wall, and
+    // the percentage of those doors that are locked.
+    pub door_chance: u32,
+    pub locked_door_chance: u32,
+    // The first feature is centered within this distance of the middle
+    // of the map.
+    pub first_feature_window: i32,
+    // Number of tiles around the map edges that features may not occupy.
+    // Must be at least 1 so that every floor can be surrounded by walls.
+    pub edge_margin: i32,
+    // Smallest width or height of a BSP leaf. Leaves are always at least
+    // this big, so rooms in them can be up to this size minus 2.
+    pub bsp_min_leaf: i32,
+    // Whether to dig tunnels to any parts of the map that can't be reached
+    // from the starting location.
+    pub repair_connectivity: bool,
+    // Number of loops to knock through walls, the maximum thickness of wall
+    // to knock through and how many times longer the walk around has to be
+    // than the new passage. See WorldMap::add_loops.
+    pub loops: u32,
+    pub loop_max_wall: i32,
+    pub loop_min_ratio: u32,
+    // Percentage of cave cells that start out as walls.
+    pub cave_fill_percent: u32,
+    // During each smoothing pass, a floor becomes a wall if at least
+    // cave_birth_limit of its 8 neighbors are walls, and a wall stays a
+    // wall if at least cave_survival_limit of them are.
+    pub cave_birth_limit: u32,
+    pub cave_survival_limit: u32,
+    pub cave_smoothing_passes: u32,
+    // Whether to join disconnected caverns with tunnels instead of keeping
+    // only the largest one.
+    pub cave_join_regions: bool,
+    // Range (inclusive) of how many monsters to spawn on a level, and how
+    // many steps away from the start they have to be.
+    pub monster_count: (u32, u32),
+    pub monster_min_distance: u32,
+    // Range (inclusive) of how many items to leave lying around a level.
+    pub item_count: (u32, u32)
+}
+
+impl GenerationConfig {
+    /// Create a random number generator seeded from the config. Each depth
+    /// gets its own sequence so levels of a dungeon can be generated in
+    /// any order.
+    pub fn rng(&self) -> StdRng {
+        StdRng::from_seed(&[self.seed, self.depth as usize][..])
+    }
+    /// Create a random number generator for filling a level with monsters
+    /// and items, separate from the one its map is laid out with.
+    pub fn population_rng(&self) -> StdRng {
+        StdRng::from_seed(&[self.seed, self.depth as usize, 1][..])
+    }
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        GenerationConfig {
+            width: 150,
+            height: 100,
+            algorithm: GenerationAlgorithm::Rooms,
+            depth: 1,
+            seed: 0,
+            attempts: 300,
+            room_weight: 1,
+            diamond_room_weight: 1,
+            circle_room_weight: 1,
+            vaults: Vec::new(),
+            room_size: (2, 12),
+            room_radius: (2, 12),
+            hallway_length: (5, 15),
+            door_chance: 50,
+            locked_door_chance: 10,
+            first_feature_window: 7,
+            edge_margin: 1,
+            bsp_min_leaf: 10,
+            repair_connectivity: true,
+            loops: 8,
+            loop_max_wall: 2,
+            loop_min_ratio: 5,
+            cave_fill_percent: 45,
+            cave_birth_limit: 5,
+            cave_survival_limit: 4,
+            cave_smoothing_passes: 4,
+            cave_join_regions: false,
+            monster_count: (4, 10),
+            monster_min_distance: 10,
+            item_count: (2, 6)
+        }
+    }
+}
+
+impl Save for GenerationAlgorithm {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(match *self {
+            GenerationAlgorithm::Rooms => 0,
+            GenerationAlgorithm::Bsp => 1,
+            GenerationAlgorithm::Caves => 2
+        });
+    }
+}
+
+impl Load for GenerationAlgorithm {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        match try!(r.token::<u8>()) {
+            0 => Ok(GenerationAlgorithm::Rooms),
+            1 => Ok(GenerationAlgorithm::Bsp),
+            2 => Ok(GenerationAlgorithm::Caves),
+            _ => corrupt("unknown generation algorithm")
+        }
+    }
+}
+
+// Vaults are left out, since they come from their own files.
+impl Save for GenerationConfig {
+    fn save(&self, w: &mut SaveWriter) {
+        self.width.save(w);
+        self.height.save(w);
+        self.algorithm.save(w);
+        self.depth.save(w);
+        self.seed.save(w);
+        self.attempts.save(w);
+        self.room_weight.save(w);
+        self.diamond_room_weight.save(w);
+        self.circle_room_weight.save(w);
+        self.room_size.save(w);
+        self.room_radius.save(w);
+        self.hallway_length.save(w);
+        self.door_chance.save(w);
+        self.locked_door_chance.save(w);
+        self.first_feature_window.save(w);
+        self.edge_margin.save(w);
+        self.bsp_min_leaf.save(w);
+        self.repair_connectivity.save(w);
+        self.loops.save(w);
+        self.loop_max_wall.save(w);
+        self.loop_min_ratio.save(w);
+        self.cave_fill_percent.save(w);
+        self.cave_birth_limit.save(w);
+        self.cave_survival_limit.save(w);
+        self.cave_smoothing_passes.save(w);
+        self.cave_join_regions.save(w);
+        self.monster_count.save(w);
+        self.monster_min_distance.save(w);
+        self.item_count.save(w);
+    }
+}
+
+impl Load for GenerationConfig {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(GenerationConfig {
+            width: try!(Load::load(r)),
+            height: try!(Load::load(r)),
+            algorithm: try!(Load::load(r)),
+            depth: try!(Load::load(r)),
+            seed: try!(Load::load(r)),
+            attempts: try!(Load::load(r)),
+            room_weight: try!(Load::load(r)),
+            diamond_room_weight: try!(Load::load(r)),
+            circle_room_weight: try!(Load::load(r)),
+            room_size: try!(Load::load(r)),
+            room_radius: try!(Load::load(r)),
+            hallway_length: try!(Load::load(r)),
+            door_chance: try!(Load::load(r)),
+            locked_door_chance: try!(Load::load(r)),
+            first_feature_window: try!(Load::load(r)),
+            edge_margin: try!(Load::load(r)),
+            bsp_min_leaf: try!(Load::load(r)),
+            repair_connectivity: try!(Load::load(r)),
+            loops: try!(Load::load(r)),
+            loop_max_wall: try!(Load::load(r)),
+            loop_min_ratio: try!(Load::load(r)),
+            cave_fill_percent: try!(Load::load(r)),
+            cave_birth_limit: try!(Load::load(r)),
+            cave_survival_limit: try!(Load::load(r)),
+            cave_smoothing_passes: try!(Load::load(r)),
+            cave_join_regions: try!(Load::load(r)),
+            monster_count: try!(Load::load(r)),
+            monster_min_distance: try!(Load::load(r)),
+            item_count: try!(Load::load(r)),
+            vaults: Vec::new()
+        })
+    }
+}
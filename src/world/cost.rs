@@ -0,0 +1,66 @@
+use tile::{Terrain, Location};
+use world_map::WorldMap;
+use std::rc::Rc;
+
+/// The cost of moving around a map, used by the pathfinding code. Moving
+/// onto a cell costs whatever the terrain function says it does for the
+/// cell's terrain, with None meaning the terrain can't be moved onto.
+/// Diagonal steps cost an extra amount on top, or aren't allowed at all if
+/// no diagonal cost is set.
+#[derive(Clone)]
+pub struct MoveCost {
+    terrain: Rc<Fn(Terrain) -> Option<u32>>,
+    diagonal: Option<u32>
+}
+
+impl MoveCost {
+    /// Create a move cost from a terrain cost function. Diagonal steps cost
+    /// the same as orthogonal ones.
+    pub fn new<F>(terrain: F) -> Self
+        where F: Fn(Terrain) -> Option<u32> + 'static
+    {
+        MoveCost {
+            terrain: Rc::new(terrain),
+            diagonal: Some(0)
+        }
+    }
+    /// Every passable cell costs 1 to move onto, in any direction.
+    pub fn uniform() -> Self {
+        MoveCost::new(|t| if t.is_passable() { Some(1) } else { None })
+    }
+    /// Cells cost as many turns as it takes to move onto their terrain.
+    pub fn terrain() -> Self {
+        MoveCost::new(|t| t.move_cost())
+    }
+    /// Set the extra cost of diagonal steps, or forbid them with None.
+    pub fn diagonal(self, diagonal: Option<u32>) -> Self {
+        MoveCost {
+            diagonal: diagonal,
+            ..self
+        }
+    }
+    pub fn allows_diagonal(&self) -> bool {
+        self.diagonal.is_some()
+    }
+    /// Return the cost of stepping between two adjacent cells, or None if
+    /// the step can't be taken.
+    pub fn step(&self, world: &WorldMap, from: Location, to: Location)
+        -> Option<u32>
+    {
+        let cost = match (self.terrain)(world.get_tile(to).terrain) {
+            Some(cost) => cost,
+            None => return None
+        };
+        if from.x == to.x || from.y == to.y {
+            Some(cost)
+        } else {
+            self.diagonal.map(|d| cost + d)
+        }
+    }
+}
+
+impl Default for MoveCost {
+    fn default() -> Self {
+        MoveCost::uniform()
+    }
+}
@@ -0,0 +1,419 @@
+use cost::MoveCost;
+use tile::Location;
+use world_map::WorldMap;
+use std::cmp::{self, Ordering};
+use std::collections::{BinaryHeap, HashSet};
+
+// Weight of cells that aren't within the limit of any goal.
+const UNREACHED: i32 = ::std::i32::MAX;
+
+/// A pathfinding map structure. A Dijkstra map lets you run pathfinding from
+/// any graph node it covers towards or away from the target nodes of the map.
+/// Currently the structure only supports underlying graphs with a fixed grid graph
+/// where the neighbors of each node must be the adjacent grid cells of that
+/// node.
+///
+/// Moving between cells costs whatever the map's MoveCost says it does, so
+/// weights are the cost of the cheapest route to a goal rather than the
+/// number of steps.
+///
+/// Distances are kept in a dense array covering the whole map. Goals can be
+/// added and removed, and cells whose terrain changed can be invalidated,
+/// without recomputing the parts of the map that aren't affected.
+///
+/// Maps can also be derived from others: flee maps lead away from the goals
+/// and combined maps mix several maps together, e.g. for monsters that want
+/// both to reach the player and to stay clear of danger. Derived maps have
+/// no goals of their own and aren't limited in range.
+#[derive(Clone)]
+pub struct Dijkstra {
+    width: i32,
+    height: i32,
+    limit: i32,
+    weights: Vec<i32>,
+    goals: HashSet<Location>,
+    cost: MoveCost
+}
+
+// An entry in the queue of cells to relax, ordered so that the BinaryHeap
+// (a max-heap) pops the closest cell first.
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct QueuedCell {
+    weight: i32,
+    loc: Location
+}
+
+impl Ord for QueuedCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.weight.cmp(&self.weight)
+            .then_with(|| (other.loc.y, other.loc.x)
+                .cmp(&(self.loc.y, self.loc.x)))
+    }
+}
+
+impl PartialOrd for QueuedCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Dijkstra {
+    /// Create a new Dijkstra map up to limit distance from goals, where
+    /// every passable cell costs 1 to move onto.
+    pub fn new(world: &WorldMap, goals: Vec<Location>, limit: u32) -> Self {
+        Dijkstra::with_cost(world, goals, limit, MoveCost::uniform())
+    }
+    /// Create a new Dijkstra map up to limit cost from goals.
+    pub fn with_cost(world: &WorldMap, goals: Vec<Location>, limit: u32,
+        cost: MoveCost) -> Self
+    {
+        assert!(goals.len() > 0);
+
+        let mut dijkstra = Dijkstra {
+            width: world.width(),
+            height: world.height(),
+            // Weights are i32s, so bigger limits mean there's no limit.
+            limit: cmp::min(limit, UNREACHED as u32) as i32,
+            weights: vec![UNREACHED; (world.width() * world.height()) as usize],
+            goals: HashSet::new(),
+            cost: cost
+        };
+        dijkstra.add_goals(world, &goals);
+
+        dijkstra
+    }
+
+    /// Return the distance from a cell to the nearest goal, or None if the
+    /// cell is out of range. Derived maps can have negative weights.
+    pub fn get(&self, node: &Location) -> Option<i32> {
+        match self.weights[self.index(*node)] {
+            UNREACHED => None,
+            w => Some(w)
+        }
+    }
+
+    /// Return the cell furthest from the goals and its distance.
+    pub fn furthest(&self) -> (Location, i32) {
+        self.weights.iter()
+            .enumerate()
+            .filter(|&(_, w)| *w != UNREACHED)
+            .max_by_key(|&(_, w)| *w)
+            .map(|(i, w)| (self.location(i), *w))
+            .unwrap()
+    }
+
+    /// Return the neighbors of a cell (if any), sorted from downhill to
+    /// uphill.
+    pub fn sorted_neighbors(& self, node: &Location) -> Vec<Location> {
+        let mut ret = Vec::new();
+        for n in self.adjacent(*node).into_iter() {
+            if let Some(w) = self.get(&n) {
+                ret.push((w, n));
+            }
+        }
+        ret.sort_by(|&(w1, _), &(w2, _)| w1.cmp(&w2));
+        ret.into_iter().map(|(_, n)| n).collect()
+    }
+
+    /// Add goals to the map, updating only the cells that get closer to a
+    /// goal.
+    pub fn add_goals(&mut self, world: &WorldMap, goals: &[Location]) {
+        let mut queue = BinaryHeap::new();
+        for goal in goals.iter() {
+            self.goals.insert(*goal);
+            let index = self.index(*goal);
+            if self.weights[index] != 0 && self.limit > 0 {
+                self.weights[index] = 0;
+                queue.push(QueuedCell { weight: 0, loc: *goal });
+            }
+        }
+        self.relax(world, queue);
+    }
+
+    /// Remove goals from the map. Locations that aren't goals are ignored.
+    pub fn remove_goals(&mut self, world: &WorldMap, goals: &[Location]) {
+        let removed: Vec<Location> = goals.iter()
+            .filter(|g| self.goals.remove(g))
+            .cloned()
+            .collect();
+        if !removed.is_empty() {
+            self.invalidate(world, &removed);
+        }
+    }
+
+    /// Update the map after the terrain at some locations changed, e.g. a
+    /// door was locked or a wall knocked down.
+    pub fn invalidate(&mut self, world: &WorldMap, changed: &[Location]) {
+        let reset = self.reset(world, changed);
+        self.reseed(world, &reset);
+    }
+
+    /// Derive a flee map, whose downhill direction leads away from the
+    /// goals. The weights are scaled by a negative coefficient and relaxed
+    /// again, so that fleeing heads for open areas far from the goals rather
+    /// than straight into the nearest dead end. Coefficients around -1.2
+    /// work well; the closer to zero, the more willing monsters are to run
+    /// past the goals to get further away.
+    pub fn flee(&self, world: &WorldMap, coefficient: f32) -> Dijkstra {
+        assert!(coefficient < 0.0);
+
+        let mut flee = Dijkstra {
+            width: self.width,
+            height: self.height,
+            limit: UNREACHED,
+            weights: self.weights.iter()
+                .map(|w| match *w {
+                    UNREACHED => UNREACHED,
+                    w => (w as f32 * coefficient).round() as i32
+                })
+                .collect(),
+            goals: HashSet::new(),
+            cost: self.cost.clone()
+        };
+        let queue = flee.weights.iter()
+            .enumerate()
+            .filter(|&(_, w)| *w != UNREACHED)
+            .map(|(i, w)| QueuedCell { weight: *w, loc: flee.location(i) })
+            .collect();
+        flee.relax(world, queue);
+
+        flee
+    }
+
+    /// Combine several maps of the same world into one by summing their
+    /// weights, each multiplied by a coefficient. A cell is in the combined
+    /// map if any of the maps reaches it. Maps that don't reach a cell count
+    /// it as being at their limit, or leave it out if they have none.
+    pub fn combine(maps: &[(&Dijkstra, f32)]) -> Dijkstra {
+        assert!(maps.len() > 0);
+        let (first, _) = maps[0];
+        assert!(maps.iter()
+            .all(|&(m, _)| m.width == first.width && m.height == first.height));
+
+        let weights = (0..first.weights.len())
+            .map(|i| {
+                if maps.iter().all(|&(m, _)| m.weights[i] == UNREACHED) {
+                    return UNREACHED;
+                }
+                maps.iter()
+                    .filter(|&&(m, _)| m.weights[i] != UNREACHED ||
+                        m.limit != UNREACHED)
+                    .map(|&(m, coefficient)| {
+                        let w = match m.weights[i] {
+                            UNREACHED => m.limit,
+                            w => w
+                        };
+                        w as f32 * coefficient
+                    })
+                    .fold(0.0, |sum, w| sum + w)
+                    .round() as i32
+            })
+            .collect();
+
+        Dijkstra {
+            width: first.width,
+            height: first.height,
+            limit: UNREACHED,
+            weights: weights,
+            goals: HashSet::new(),
+            cost: first.cost.clone()
+        }
+    }
+
+    // Clear the weights of the given cells and of every cell whose weight
+    // may have come from them, returning all of the cleared cells.
+    fn reset(&mut self, world: &WorldMap, roots: &[Location]) -> Vec<Location> {
+        let mut reset = Vec::new();
+        let mut stack: Vec<(Location, i32)> = roots.iter()
+            .map(|r| (*r, self.weights[self.index(*r)]))
+            .collect();
+        for root in roots.iter() {
+            let index = self.index(*root);
+            self.weights[index] = UNREACHED;
+        }
+
+        while let Some((loc, weight)) = stack.pop() {
+            reset.push(loc);
+            if weight == UNREACHED {
+                continue;
+            }
+            for adj in world.get_adjacent(loc, true).into_iter() {
+                let index = self.index(adj);
+                let adj_weight = self.weights[index];
+                let from_here = self.cost.step(world, loc, adj)
+                    .map(|c| weight + c as i32);
+                if adj_weight != UNREACHED && from_here == Some(adj_weight) &&
+                    !self.goals.contains(&adj) {
+                    self.weights[index] = UNREACHED;
+                    stack.push((adj, adj_weight));
+                }
+            }
+        }
+
+        reset
+    }
+
+    // Give cleared cells their weights back from their neighbors and spread
+    // the change out from there.
+    fn reseed(&mut self, world: &WorldMap, cells: &[Location]) {
+        let mut queue = BinaryHeap::new();
+        for loc in cells.iter() {
+            let index = self.index(*loc);
+            let weight = if self.goals.contains(loc) {
+                0
+            } else {
+                world.get_adjacent(*loc, true).into_iter()
+                    .filter(|l| self.weights[self.index(*l)] != UNREACHED)
+                    .filter_map(|l| self.cost.step(world, l, *loc)
+                        .map(|c| self.weights[self.index(l)] + c as i32))
+                    .min()
+                    .unwrap_or(UNREACHED)
+            };
+            if weight < self.limit && weight < self.weights[index] {
+                self.weights[index] = weight;
+                queue.push(QueuedCell { weight: weight, loc: *loc });
+            }
+        }
+        self.relax(world, queue);
+    }
+
+    // Spread weights outwards from the queued cells until nothing else can
+    // get closer to a goal.
+    fn relax(&mut self, world: &WorldMap, mut queue: BinaryHeap<QueuedCell>) {
+        while let Some(cell) = queue.pop() {
+            if cell.weight > self.weights[self.index(cell.loc)] {
+                continue;
+            }
+
+            for adj in world.get_adjacent(cell.loc, true).into_iter() {
+                let weight = match self.cost.step(world, cell.loc, adj) {
+                    Some(c) => cell.weight.saturating_add(c as i32),
+                    None => continue
+                };
+                let index = self.index(adj);
+                if weight < self.limit && weight < self.weights[index] {
+                    self.weights[index] = weight;
+                    queue.push(QueuedCell { weight: weight, loc: adj });
+                }
+            }
+        }
+    }
+
+    fn adjacent(&self, loc: Location) -> Vec<Location> {
+        let mut adjacent = Vec::new();
+        for i in -1..2 {
+            for j in -1..2 {
+                let adj = Location::new(loc.x + i, loc.y + j);
+                let is_diag = i != 0 && j != 0;
+                if (i != 0 || j != 0) && adj.x >= 0 && adj.x < self.width &&
+                    adj.y >= 0 && adj.y < self.height &&
+                    (!is_diag || self.cost.allows_diagonal()) {
+                    adjacent.push(adj);
+                }
+            }
+        }
+
+        adjacent
+    }
+    fn index(&self, loc: Location) -> usize {
+        (loc.y * self.width + loc.x) as usize
+    }
+    fn location(&self, index: usize) -> Location {
+        Location::new(index as i32 % self.width, index as i32 / self.width)
+    }
+}
+
+#[test]
+fn test_incremental_updates() {
+    use config::GenerationConfig;
+    use random::IterRandomExt;
+    use tile::Terrain;
+
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        seed: 4,
+        ..Default::default()
+    };
+    let (mut world, start) = WorldMap::generate(&config);
+    let mut rng = config.rng();
+    let floors: Vec<Location> = world.tiles()
+        .filter(|t| t.terrain == Terrain::Floor)
+        .map(|t| t.loc)
+        .collect();
+    let assert_matches_fresh = |dmap: &Dijkstra, world: &WorldMap| {
+        let goals: Vec<Location> = dmap.goals.iter().cloned().collect();
+        let fresh = Dijkstra::with_cost(world, goals, dmap.limit as u32,
+            dmap.cost.clone());
+        assert!(dmap.weights == fresh.weights);
+    };
+
+    let mut goals = vec![start];
+    let cost = MoveCost::terrain().diagonal(Some(1));
+    let mut dmap = Dijkstra::with_cost(&world, goals.clone(), 60, cost);
+    for i in 0..60 {
+        match i % 3 {
+            0 => {
+                let goal = *floors.iter().random(&mut rng);
+                goals.push(goal);
+                dmap.add_goals(&world, &[goal]);
+            },
+            1 if goals.len() > 1 => {
+                let goal = goals.remove(0);
+                dmap.remove_goals(&world, &[goal]);
+            },
+            _ => {
+                // Turn floors into water, water into walls and walls back
+                // into floors.
+                let loc = *floors.iter().random(&mut rng);
+                let tile = world.get_tile_mut(loc);
+                tile.terrain = match tile.terrain {
+                    Terrain::Floor => Terrain::ShallowWater,
+                    Terrain::ShallowWater => Terrain::Wall,
+                    _ => Terrain::Floor
+                };
+                dmap.invalidate(&world, &[loc]);
+            }
+        }
+        assert_matches_fresh(&dmap, &world);
+    }
+}
+
+#[test]
+fn test_flee_and_combine() {
+    use config::GenerationConfig;
+
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        seed: 6,
+        ..Default::default()
+    };
+    let (world, player) = WorldMap::generate(&config);
+    let chase = Dijkstra::new(&world, vec![player], 100);
+
+    // Running downhill on the flee map from next to the player ends up far
+    // away from them.
+    let flee = chase.flee(&world, -1.2);
+    let mut loc = chase.sorted_neighbors(&player)[1];
+    loop {
+        let next = flee.sorted_neighbors(&loc)[0];
+        if flee.get(&next) >= flee.get(&loc) {
+            break;
+        }
+        loc = next;
+    }
+    assert!(chase.get(&loc).unwrap() > 10);
+
+    // Combining a map with itself halved twice gives the map back.
+    let same = Dijkstra::combine(&[(&chase, 0.5), (&chase, 0.5)]);
+    assert!(same.weights == chase.weights);
+    let away = Dijkstra::combine(&[(&chase, 1.0), (&flee, 1.0)]);
+    assert!(away.get(&player).unwrap() <= 0);
+    // Huge limits reach everything instead of wrapping around to nothing.
+    let unlimited = Dijkstra::new(&world, vec![player], ::std::u32::MAX);
+    let limit = (world.width() * world.height()) as u32;
+    let limited = Dijkstra::new(&world, vec![player], limit);
+    assert!(unlimited.weights == limited.weights);
+    assert!(unlimited.furthest().1 > 10);
+}
@@ -0,0 +1,350 @@
+extern crate rand;
+
+use config::GenerationConfig;
+use ecs::{Ecs, Entity, Renderable, Health, CombatStats, BlocksMovement,
+    Inventory, Actor};
+use item::{item_table, spawn_item};
+use tile::{Terrain, Location};
+use monster::{monster_table, spawn_monster};
+use world_map::WorldMap;
+use vault::Vault;
+use save::{Save, Load, SaveWriter, SaveReader, SaveError, corrupt};
+use dijkstra::Dijkstra;
+use std::collections::{HashMap, HashSet, VecDeque};
+use self::rand::Rng;
+
+/// A single level of a dungeon.
+pub struct Level {
+    pub world: WorldMap,
+    pub depth: u32,
+    // Where the player arrives when coming down from the level above. This
+    // is the up staircase on every level but the first.
+    pub start: Location,
+    pub down_stairs: Location,
+    // Locations the player has seen on this level.
+    pub explored: HashSet<Location>,
+    // How the items lying on explored locations looked when the player
+    // last saw them.
+    pub remembered_items: HashMap<Location, Renderable>,
+    // Everything that lives on the level, including the player while
+    // they're on it.
+    pub entities: Ecs
+}
+
+impl Level {
+    fn generate(config: &GenerationConfig) -> Self {
+        let (mut world, start) = WorldMap::generate(config);
+        let mut rng = config.population_rng();
+
+        // Put the down staircase as far from the start as possible.
+        let limit = (world.width() * world.height()) as u32;
+        let dmap = Dijkstra::new(&world, vec![start], limit);
+        let (mut down_stairs, _) = dmap.furthest();
+        if down_stairs == start {
+            // Nothing else can be reached (e.g. caves too full to have more
+            // than one floor), so dig out a spot next to the start.
+            let (width, height) = (world.width(), world.height());
+            down_stairs = world.get_adjacent(start, false).into_iter()
+                .find(|l| l.x > 0 && l.y > 0 &&
+                    l.x < width - 1 && l.y < height - 1)
+                .expect("map is too small for stairs");
+            for adj in world.get_adjacent(down_stairs, true) {
+                if world.get_tile(adj).terrain == Terrain::Nothing {
+                    world.get_tile_mut(adj).terrain = Terrain::Wall;
+                }
+            }
+        }
+        world.get_tile_mut(down_stairs).terrain = Terrain::StairsDown;
+        if config.depth > 1 {
+            world.get_tile_mut(start).terrain = Terrain::StairsUp;
+        }
+
+        // Scatter monsters around, away from where the player arrives.
+        let mut entities = Ecs::new();
+        let mut spots: Vec<Location> = world.tiles()
+            .filter(|t| t.terrain == Terrain::Floor)
+            .map(|t| t.loc)
+            .filter(|l| dmap.get(l)
+                .map_or(false, |d| d >= config.monster_min_distance as i32))
+            .collect();
+        let table = monster_table(config.depth);
+        let count = rng.gen_range::<u32>(config.monster_count.0,
+            config.monster_count.1 + 1);
+        for _ in 0..count {
+            if spots.is_empty() {
+                break;
+            }
+            let spot = spots.swap_remove(rng.gen_range(0, spots.len()));
+            let kind = table.generate(&mut rng);
+            spawn_monster(&mut entities, &kind, spot);
+        }
+
+        // Leave items lying around anywhere.
+        let mut spots: Vec<Location> = world.tiles()
+            .filter(|t| t.terrain == Terrain::Floor)
+            .map(|t| t.loc)
+            .collect();
+        let table = item_table(config.depth);
+        let count = rng.gen_range::<u32>(config.item_count.0,
+            config.item_count.1 + 1);
+        for _ in 0..count {
+            if spots.is_empty() {
+                break;
+            }
+            let spot = spots.swap_remove(rng.gen_range(0, spots.len()));
+            let kind = table.generate(&mut rng);
+            spawn_item(&mut entities, &kind, spot);
+        }
+
+        Level {
+            world: world,
+            depth: config.depth,
+            start: start,
+            down_stairs: down_stairs,
+            explored: HashSet::new(),
+            remembered_items: HashMap::new(),
+            entities: entities
+        }
+    }
+    // Find the closest passable location to loc that nothing is blocking,
+    // searching outward from loc itself.
+    fn free_spot_near(&self, loc: Location) -> Location {
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::new();
+        seen.insert(loc);
+        queue.push_back(loc);
+        while let Some(next) = queue.pop_front() {
+            if !self.entities.is_blocked(next) {
+                return next;
+            }
+            for adj in self.world.get_adjacent(next, true) {
+                if self.world.get_tile(adj).terrain.is_passable() &&
+                    seen.insert(adj) {
+                    queue.push_back(adj);
+                }
+            }
+        }
+
+        // Everywhere is blocked, so share the spot.
+        loc
+    }
+}
+
+/// A stack of levels connected by stairs. Levels are generated the first
+/// time they are visited and kept around afterwards. The player lives in
+/// the entities of the current level and moves along when it changes.
+pub struct Dungeon {
+    config: GenerationConfig,
+    levels: Vec<Level>,
+    current: usize,
+    player: Entity
+}
+
+impl Dungeon {
+    /// Create a dungeon, generating its first level from the config.
+    pub fn new(config: GenerationConfig) -> Self {
+        let mut first = Level::generate(
+            &GenerationConfig { depth: 1, ..config.clone() });
+        let player = first.entities.create();
+        first.entities.renderables.insert(player,
+            Renderable { glyph: '@', color: (255, 255, 255) });
+        first.entities.names.insert(player, "you".to_string());
+        first.entities.healths.insert(player, Health { current: 30, max: 30 });
+        first.entities.combat.insert(player,
+            CombatStats { attack: 6, defense: 1 });
+        first.entities.blockers.insert(player, BlocksMovement);
+        first.entities.inventories.insert(player,
+            Inventory { items: Vec::new(), capacity: 26 });
+        first.entities.actors.insert(player, Actor { speed: 100, energy: 0 });
+        let start = first.start;
+        first.entities.set_position(player, start);
+
+        Dungeon {
+            config: config,
+            levels: vec![first],
+            current: 0,
+            player: player
+        }
+    }
+    pub fn level(&self) -> &Level {
+        &self.levels[self.current]
+    }
+    pub fn level_mut(&mut self) -> &mut Level {
+        &mut self.levels[self.current]
+    }
+    pub fn depth(&self) -> u32 {
+        self.level().depth
+    }
+    /// Return the player entity, which lives on the current level.
+    pub fn player(&self) -> Entity {
+        self.player
+    }
+    pub fn player_loc(&self) -> Location {
+        self.level().entities.position(self.player).unwrap()
+    }
+    /// Go down a level, generating it if needed. Returns where the player
+    /// arrives: the up stairs, or the nearest free spot if something is
+    /// standing on them.
+    pub fn descend(&mut self) -> Location {
+        if self.current + 1 == self.levels.len() {
+            let config = GenerationConfig {
+                depth: self.current as u32 + 2,
+                ..self.config.clone()
+            };
+            self.levels.push(Level::generate(&config));
+        }
+        let (from, to) = (self.current, self.current + 1);
+        let stairs = self.levels[to].start;
+        self.move_player(from, to, stairs)
+    }
+    /// Go up a level. Returns where the player arrives, or None if already
+    /// on the first level.
+    pub fn ascend(&mut self) -> Option<Location> {
+        if self.current == 0 {
+            return None;
+        }
+        let (from, to) = (self.current, self.current - 1);
+        let stairs = self.levels[to].down_stairs;
+        Some(self.move_player(from, to, stairs))
+    }
+    /// Replace the vaults used for levels that haven't been generated yet.
+    pub fn set_vaults(&mut self, vaults: Vec<Vault>) {
+        self.config.vaults = vaults;
+    }
+    // Move the player to another level, as close to the stairs as possible.
+    // Returns where they end up.
+    fn move_player(&mut self, from: usize, to: usize, stairs: Location)
+        -> Location {
+        let player = {
+            let (low, high) = self.levels.split_at_mut(from.max(to));
+            let (from_level, to_level) = if from < to {
+                (&mut low[from], &mut high[0])
+            } else {
+                (&mut high[0], &mut low[to])
+            };
+            from_level.entities.transfer(self.player, &mut to_level.entities)
+        };
+        let arrival = self.levels[to].free_spot_near(stairs);
+        self.levels[to].entities.set_position(player, arrival);
+        self.player = player;
+        self.current = to;
+
+        arrival
+    }
+}
+
+impl Save for Level {
+    fn save(&self, w: &mut SaveWriter) {
+        self.world.save(w);
+        w.token(self.depth);
+        self.start.save(w);
+        self.down_stairs.save(w);
+        self.explored.save(w);
+        self.remembered_items.save(w);
+        self.entities.save(w);
+    }
+}
+
+impl Load for Level {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Level {
+            world: try!(Load::load(r)),
+            depth: try!(r.token()),
+            start: try!(Load::load(r)),
+            down_stairs: try!(Load::load(r)),
+            explored: try!(Load::load(r)),
+            remembered_items: try!(Load::load(r)),
+            entities: try!(Load::load(r))
+        })
+    }
+}
+
+impl Save for Dungeon {
+    fn save(&self, w: &mut SaveWriter) {
+        self.config.save(w);
+        self.levels.save(w);
+        w.token(self.current);
+        self.player.save(w);
+    }
+}
+
+impl Load for Dungeon {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let dungeon = Dungeon {
+            config: try!(Load::load(r)),
+            levels: try!(Load::load(r)),
+            current: try!(r.token()),
+            player: try!(Load::load(r))
+        };
+        match dungeon.levels.get(dungeon.current) {
+            Some(level) if level.entities.position(dungeon.player)
+                .is_some() => Ok(dungeon),
+            _ => corrupt("the player is missing")
+        }
+    }
+}
+
+#[test]
+fn test_stairs_connect_levels() {
+    use config::GenerationAlgorithm;
+
+    let config = GenerationConfig {
+        width: 80,
+        height: 50,
+        seed: 3,
+        ..Default::default()
+    };
+    let mut dungeon = Dungeon::new(config.clone());
+    assert_eq!(dungeon.depth(), 1);
+    assert!(dungeon.ascend().is_none());
+
+    let down = dungeon.level().down_stairs;
+    assert_eq!(dungeon.level().world.get_tile(down).terrain,
+        Terrain::StairsDown);
+    dungeon.level_mut().explored.insert(down);
+
+    let arrival = dungeon.descend();
+    assert_eq!(dungeon.depth(), 2);
+    assert_eq!(dungeon.player_loc(), arrival);
+    assert_eq!(dungeon.level().world.get_tile(arrival).terrain,
+        Terrain::StairsUp);
+
+    // Going back up lands on the down stairs with the level as it was.
+    assert_eq!(dungeon.ascend(), Some(down));
+    assert!(dungeon.level().explored.contains(&down));
+    assert_eq!(dungeon.player_loc(), down);
+    assert!(dungeon.level().entities.monsters.iter().count() > 0);
+    assert!(!dungeon.level().entities.monsters.contains(dungeon.player()));
+
+    // Something standing on the stairs makes the player arrive beside it.
+    dungeon.descend();
+    let blocker = dungeon.levels[0].entities.create();
+    dungeon.levels[0].entities.blockers.insert(blocker, BlocksMovement);
+    dungeon.levels[0].entities.set_position(blocker, down);
+    let arrival = dungeon.ascend().unwrap();
+    assert_eq!(arrival.chebyshev(&down), 1);
+    assert_eq!(dungeon.player_loc(), arrival);
+    assert!(dungeon.level().world.get_tile(arrival).terrain.is_passable());
+
+    // Even a level with only one floor has a way down.
+    let level = Level::generate(&GenerationConfig {
+        algorithm: GenerationAlgorithm::Caves,
+        cave_fill_percent: 100,
+        depth: 2,
+        ..config.clone()
+    });
+    assert!(level.down_stairs != level.start);
+    assert_eq!(level.down_stairs.manhattan(&level.start), 1);
+    assert_eq!(level.world.get_tile(level.down_stairs).terrain,
+        Terrain::StairsDown);
+    assert_eq!(level.world.get_tile(level.start).terrain, Terrain::StairsUp);
+
+    // Monster and item counts are inclusive, so a level can be left empty.
+    let level = Level::generate(&GenerationConfig {
+        monster_count: (0, 0),
+        item_count: (0, 0),
+        ..config
+    });
+    assert_eq!(level.entities.monsters.iter().count(), 0);
+    assert_eq!(level.entities.items.iter().count(), 0);
+}
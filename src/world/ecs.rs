@@ -0,0 +1,577 @@
+use tile::Location;
+use save::{Save, Load, SaveWriter, SaveReader, SaveError, corrupt};
+use std::collections::HashMap;
+
+/// Something that lives in the world, like the player, a monster or an
+/// item. An entity is just an id tying its components together. The id
+/// holds an index, which gets reused once the entity is destroyed, and a
+/// generation, which tells the entity apart from others that used the same
+/// index before or after it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Entity {
+    id: u64
+}
+
+impl Entity {
+    fn new(index: u32, generation: u32) -> Self {
+        Entity {id: (generation as u64) << 32 | index as u64}
+    }
+    fn index(&self) -> usize {
+        (self.id & 0xffffffff) as usize
+    }
+    fn generation(&self) -> u32 {
+        (self.id >> 32) as u32
+    }
+}
+
+/// How an entity is drawn.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Renderable {
+    pub glyph: char,
+    pub color: (u8, u8, u8)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Health {
+    pub current: i32,
+    pub max: i32
+}
+
+/// How well an entity fights in melee. See combat::attack.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CombatStats {
+    pub attack: i32,
+    pub defense: i32
+}
+
+/// What happens when an item gets used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ItemEffect {
+    Heal(i32),
+    RaiseAttack(i32),
+    RaiseDefense(i32)
+}
+
+/// Marks entities that can be picked up and used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Item {
+    pub effect: ItemEffect
+}
+
+/// The items an entity is carrying. Carried items have no position.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Inventory {
+    pub items: Vec<Entity>,
+    pub capacity: usize
+}
+
+/// Gives an entity turns of its own. See Scheduler.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Actor {
+    pub speed: i32,
+    pub energy: i32
+}
+
+/// Marks entities that nothing else can move onto.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BlocksMovement;
+
+/// Marks entities that act on their own, chasing the player once they see
+/// them within sight distance.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Monster {
+    pub sight: i32
+}
+
+/// Storage for one type of component, indexed by entity.
+pub struct Storage<T> {
+    components: Vec<Option<(Entity, T)>>
+}
+
+impl<T> Storage<T> {
+    pub fn new() -> Self {
+        Storage {components: Vec::new()}
+    }
+    /// Give an entity a component, returning the one it had before.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        let index = entity.index();
+        while self.components.len() <= index {
+            self.components.push(None);
+        }
+        let old = self.remove(entity);
+        self.components[index] = Some((entity, component));
+        old
+    }
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.components[entity.index()].take().map(|(_, c)| c)
+    }
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.get(entity).is_some()
+    }
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.components.get(entity.index()) {
+            Some(&Some((e, ref c))) if e == entity => Some(c),
+            _ => None
+        }
+    }
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.components.get_mut(entity.index()) {
+            Some(&mut Some((e, ref mut c))) if e == entity => Some(c),
+            _ => None
+        }
+    }
+    /// Iterate over the entities that have this component, in order of
+    /// index. Destroyed entities' indices are reused, so this isn't
+    /// necessarily the order they were created in.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a T)> + 'a> {
+        Box::new(self.components.iter()
+            .filter_map(|c| c.as_ref().map(|&(e, ref c)| (e, c))))
+    }
+    pub fn iter_mut<'a>(&'a mut self)
+        -> Box<Iterator<Item=(Entity, &'a mut T)> + 'a>
+    {
+        Box::new(self.components.iter_mut()
+            .filter_map(|c| c.as_mut().map(|&mut (e, ref mut c)| (e, c))))
+    }
+    /// Find the entities that have both this component and another one.
+    pub fn join<'a, U>(&'a self, other: &'a Storage<U>)
+        -> Vec<(Entity, &'a T, &'a U)>
+    {
+        self.iter()
+            .filter_map(|(e, c)| other.get(e).map(|o| (e, c, o)))
+            .collect()
+    }
+}
+
+/// The entities of a level and their components. Positions are kept
+/// separately from the other components so that the spatial index always
+/// matches them.
+pub struct Ecs {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    // Indices of destroyed entities, ready to be reused.
+    free: Vec<u32>,
+    positions: Storage<Location>,
+    spatial: HashMap<Location, Vec<Entity>>,
+    pub renderables: Storage<Renderable>,
+    pub names: Storage<String>,
+    pub healths: Storage<Health>,
+    pub combat: Storage<CombatStats>,
+    pub blockers: Storage<BlocksMovement>,
+    pub monsters: Storage<Monster>,
+    pub items: Storage<Item>,
+    pub inventories: Storage<Inventory>,
+    pub actors: Storage<Actor>
+}
+
+impl Ecs {
+    pub fn new() -> Self {
+        Ecs {
+            generations: Vec::new(),
+            alive: Vec::new(),
+            free: Vec::new(),
+            positions: Storage::new(),
+            spatial: HashMap::new(),
+            renderables: Storage::new(),
+            names: Storage::new(),
+            healths: Storage::new(),
+            combat: Storage::new(),
+            blockers: Storage::new(),
+            monsters: Storage::new(),
+            items: Storage::new(),
+            inventories: Storage::new(),
+            actors: Storage::new()
+        }
+    }
+    /// Create an entity with no components.
+    pub fn create(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                let i = index as usize;
+                self.generations[i] += 1;
+                self.alive[i] = true;
+                Entity::new(index, self.generations[i])
+            },
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity::new(self.generations.len() as u32 - 1, 0)
+            }
+        }
+    }
+    /// Destroy an entity along with all of its components and anything it
+    /// was carrying.
+    pub fn destroy(&mut self, entity: Entity) {
+        if !self.is_alive(entity) {
+            return;
+        }
+        if let Some(inventory) = self.inventories.remove(entity) {
+            for item in inventory.items {
+                self.destroy(item);
+            }
+        }
+        self.remove_position(entity);
+        self.renderables.remove(entity);
+        self.names.remove(entity);
+        self.healths.remove(entity);
+        self.combat.remove(entity);
+        self.blockers.remove(entity);
+        self.monsters.remove(entity);
+        self.items.remove(entity);
+        self.actors.remove(entity);
+        self.alive[entity.index()] = false;
+        self.free.push(entity.index() as u32);
+    }
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        let index = entity.index();
+        index < self.alive.len() && self.alive[index] &&
+            self.generations[index] == entity.generation()
+    }
+    /// Return every living entity, in order of index (see Storage::iter).
+    pub fn entities(&self) -> Vec<Entity> {
+        (0..self.alive.len())
+            .filter(|i| self.alive[*i])
+            .map(|i| Entity::new(i as u32, self.generations[i]))
+            .collect()
+    }
+
+    pub fn positions(&self) -> &Storage<Location> {
+        &self.positions
+    }
+    pub fn position(&self, entity: Entity) -> Option<Location> {
+        self.positions.get(entity).cloned()
+    }
+    /// Put an entity somewhere, moving it if it was already placed.
+    pub fn set_position(&mut self, entity: Entity, loc: Location) {
+        assert!(self.is_alive(entity));
+        self.remove_position(entity);
+        self.positions.insert(entity, loc);
+        self.spatial.entry(loc).or_insert(Vec::new()).push(entity);
+    }
+    /// Take an entity out of the world without destroying it, e.g. when it
+    /// gets picked up.
+    pub fn remove_position(&mut self, entity: Entity) -> Option<Location> {
+        let loc = self.positions.remove(entity);
+        if let Some(loc) = loc {
+            let now_empty = {
+                let here = self.spatial.get_mut(&loc).unwrap();
+                here.retain(|e| *e != entity);
+                here.is_empty()
+            };
+            if now_empty {
+                self.spatial.remove(&loc);
+            }
+        }
+        loc
+    }
+    /// Return the entities at a location, in the order they got there.
+    pub fn entities_at(&self, loc: Location) -> &[Entity] {
+        self.spatial.get(&loc).map_or(&[], |e| &e[..])
+    }
+    /// Whether an entity at a location stops others moving onto it.
+    pub fn is_blocked(&self, loc: Location) -> bool {
+        self.entities_at(loc).iter().any(|e| self.blockers.contains(*e))
+    }
+
+    /// Move an entity and all of its components into another Ecs, e.g.
+    /// when the player changes levels. Anything it's carrying comes along.
+    /// Returns the entity's handle in the other Ecs; it's left without a
+    /// position.
+    pub fn transfer(&mut self, entity: Entity, other: &mut Ecs) -> Entity {
+        assert!(self.is_alive(entity));
+        let moved = other.create();
+        if let Some(inventory) = self.inventories.remove(entity) {
+            let items = inventory.items.iter()
+                .map(|i| self.transfer(*i, other))
+                .collect();
+            other.inventories.insert(moved,
+                Inventory { items: items, ..inventory });
+        }
+        if let Some(c) = self.renderables.remove(entity) {
+            other.renderables.insert(moved, c);
+        }
+        if let Some(c) = self.healths.remove(entity) {
+            other.healths.insert(moved, c);
+        }
+        if let Some(c) = self.combat.remove(entity) {
+            other.combat.insert(moved, c);
+        }
+        if let Some(c) = self.names.remove(entity) {
+            other.names.insert(moved, c);
+        }
+        if let Some(c) = self.blockers.remove(entity) {
+            other.blockers.insert(moved, c);
+        }
+        if let Some(c) = self.monsters.remove(entity) {
+            other.monsters.insert(moved, c);
+        }
+        if let Some(c) = self.items.remove(entity) {
+            other.items.insert(moved, c);
+        }
+        if let Some(c) = self.actors.remove(entity) {
+            other.actors.insert(moved, c);
+        }
+        self.destroy(entity);
+
+        moved
+    }
+}
+
+impl Save for Entity {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.id);
+    }
+}
+
+impl Load for Entity {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Entity {id: try!(r.token())})
+    }
+}
+
+impl Save for Renderable {
+    fn save(&self, w: &mut SaveWriter) {
+        self.glyph.save(w);
+        self.color.save(w);
+    }
+}
+
+impl Load for Renderable {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Renderable {
+            glyph: try!(Load::load(r)),
+            color: try!(Load::load(r))
+        })
+    }
+}
+
+impl Save for Health {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.current);
+        w.token(self.max);
+    }
+}
+
+impl Load for Health {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Health {current: try!(r.token()), max: try!(r.token())})
+    }
+}
+
+impl Save for CombatStats {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.attack);
+        w.token(self.defense);
+    }
+}
+
+impl Load for CombatStats {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(CombatStats {attack: try!(r.token()), defense: try!(r.token())})
+    }
+}
+
+impl Save for Item {
+    fn save(&self, w: &mut SaveWriter) {
+        let (kind, amount) = match self.effect {
+            ItemEffect::Heal(amount) => (0, amount),
+            ItemEffect::RaiseAttack(amount) => (1, amount),
+            ItemEffect::RaiseDefense(amount) => (2, amount)
+        };
+        w.token(kind);
+        w.token(amount);
+    }
+}
+
+impl Load for Item {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let kind = try!(r.token::<u8>());
+        let amount = try!(r.token());
+        let effect = match kind {
+            0 => ItemEffect::Heal(amount),
+            1 => ItemEffect::RaiseAttack(amount),
+            2 => ItemEffect::RaiseDefense(amount),
+            _ => return corrupt("unknown item effect")
+        };
+        Ok(Item {effect: effect})
+    }
+}
+
+impl Save for Inventory {
+    fn save(&self, w: &mut SaveWriter) {
+        self.items.save(w);
+        w.token(self.capacity);
+    }
+}
+
+impl Load for Inventory {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Inventory {items: try!(Load::load(r)), capacity: try!(r.token())})
+    }
+}
+
+impl Save for Actor {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.speed);
+        w.token(self.energy);
+    }
+}
+
+impl Load for Actor {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Actor {speed: try!(r.token()), energy: try!(r.token())})
+    }
+}
+
+// Marker components have nothing to save; whether an entity has one is
+// saved by the storage.
+impl Save for BlocksMovement {
+    fn save(&self, _: &mut SaveWriter) {}
+}
+
+impl Load for BlocksMovement {
+    fn load(_: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(BlocksMovement)
+    }
+}
+
+impl Save for Monster {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.sight);
+    }
+}
+
+impl Load for Monster {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Monster {sight: try!(r.token())})
+    }
+}
+
+impl<T: Save> Save for Storage<T> {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.iter().count());
+        for (e, c) in self.iter() {
+            e.save(w);
+            c.save(w);
+        }
+    }
+}
+
+impl Ecs {
+    // Read back a storage saved with the Ecs, checking that its entities
+    // are still around.
+    fn load_storage<T: Load>(&self, r: &mut SaveReader)
+        -> Result<Storage<T>, SaveError>
+    {
+        let mut storage = Storage::new();
+        let components: Vec<(Entity, T)> = try!(Load::load(r));
+        for (e, c) in components {
+            if !self.is_alive(e) {
+                return corrupt("component belongs to a dead entity");
+            }
+            storage.insert(e, c);
+        }
+        Ok(storage)
+    }
+}
+
+// Positions are saved as the spatial index, which keeps the order entities
+// got to each location in.
+impl Save for Ecs {
+    fn save(&self, w: &mut SaveWriter) {
+        self.generations.save(w);
+        self.alive.save(w);
+        self.free.save(w);
+        self.spatial.save(w);
+        self.renderables.save(w);
+        self.names.save(w);
+        self.healths.save(w);
+        self.combat.save(w);
+        self.blockers.save(w);
+        self.monsters.save(w);
+        self.items.save(w);
+        self.inventories.save(w);
+        self.actors.save(w);
+    }
+}
+
+impl Load for Ecs {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let mut ecs = Ecs::new();
+        ecs.generations = try!(Load::load(r));
+        ecs.alive = try!(Load::load(r));
+        ecs.free = try!(Load::load(r));
+        let count = ecs.generations.len();
+        let bad_free = |i: &u32| *i as usize >= count || ecs.alive[*i as usize];
+        if ecs.alive.len() != count || ecs.free.iter().any(bad_free) {
+            return corrupt("entities don't add up");
+        }
+        let spatial: HashMap<Location, Vec<Entity>> = try!(Load::load(r));
+        for (loc, entities) in spatial {
+            for e in entities {
+                if !ecs.is_alive(e) || ecs.positions.contains(e) {
+                    return corrupt("invalid entity position");
+                }
+                ecs.set_position(e, loc);
+            }
+        }
+        ecs.renderables = try!(ecs.load_storage(r));
+        ecs.names = try!(ecs.load_storage(r));
+        ecs.healths = try!(ecs.load_storage(r));
+        ecs.combat = try!(ecs.load_storage(r));
+        ecs.blockers = try!(ecs.load_storage(r));
+        ecs.monsters = try!(ecs.load_storage(r));
+        ecs.items = try!(ecs.load_storage(r));
+        ecs.inventories = try!(ecs.load_storage::<Inventory>(r));
+        if ecs.inventories.iter()
+            .any(|(_, i)| i.items.iter().any(|e| !ecs.is_alive(*e))) {
+            return corrupt("inventory holds a dead entity");
+        }
+        ecs.actors = try!(ecs.load_storage(r));
+        Ok(ecs)
+    }
+}
+
+#[test]
+fn test_ecs() {
+    let mut ecs = Ecs::new();
+    let a = ecs.create();
+    let b = ecs.create();
+    let here = Location::new(3, 4);
+    ecs.set_position(a, here);
+    ecs.set_position(b, here);
+    ecs.healths.insert(b, Health {current: 5, max: 10});
+    assert_eq!(ecs.entities_at(here), &[a, b]);
+    assert!(!ecs.is_blocked(here));
+    ecs.blockers.insert(a, BlocksMovement);
+    assert!(ecs.is_blocked(here));
+
+    // Moving updates the spatial index.
+    let there = Location::new(4, 4);
+    ecs.set_position(a, there);
+    assert_eq!(ecs.entities_at(here), &[b]);
+    assert_eq!(ecs.entities_at(there), &[a]);
+    assert!(!ecs.is_blocked(here));
+
+    let joined = ecs.positions().join(&ecs.healths);
+    assert_eq!(joined.len(), 1);
+    assert_eq!(joined[0].0, b);
+
+    // Destroyed entities' indices get reused, but old handles stay dead.
+    ecs.destroy(b);
+    assert!(ecs.entities_at(here).is_empty());
+    let c = ecs.create();
+    assert!(c != b);
+    assert!(!ecs.is_alive(b));
+    assert!(ecs.healths.get(b).is_none());
+    assert_eq!(ecs.entities(), vec![a, c]);
+
+    let mut other = Ecs::new();
+    let moved = ecs.transfer(a, &mut other);
+    assert!(!ecs.is_alive(a));
+    assert!(!ecs.is_blocked(there));
+    assert!(other.blockers.contains(moved));
+}
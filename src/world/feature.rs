@@ -58,17 +58,12 @@ impl Feature {
     pub fn hallway(length: i32, is_horiz: bool) -> Self {
         assert!(length > 0);
         let mut tiles = Vec::new();
-        if is_horiz {
-            for i in 0..length {
-                tiles.push(Tile::new(Location::new(i, 0), Terrain::Floor));
-            }
-        } else {
-            for i in 0..length {
-                tiles.push(Tile::new(Location::new(0, i), Terrain::Floor));
-            }
+        for i in 0..length {
+            tiles.push(Tile::new(Location::new(i, 0), Terrain::Floor));
         }
 
-        Feature {tiles: tiles}
+        let hallway = Feature {tiles: tiles};
+        if is_horiz { hallway } else { hallway.rotate(1) }
     }
     pub fn translate(&self, x: i32, y: i32) -> Self {
         Feature {
@@ -78,6 +73,48 @@ impl Feature {
                 .collect()
         }
     }
+    /// Rotate the feature clockwise by the given number of quarter turns.
+    /// The top left corner of the feature's bounding box stays put.
+    pub fn rotate(&self, quarter_turns: u32) -> Self {
+        let rotated = Feature {
+            tiles: self.tiles.iter()
+                .map(|t| {
+                    let loc = match quarter_turns % 4 {
+                        0 => t.loc,
+                        1 => Location::new(-t.loc.y, t.loc.x),
+                        2 => Location::new(-t.loc.x, -t.loc.y),
+                        _ => Location::new(t.loc.y, -t.loc.x)
+                    };
+                    Tile::new(loc, t.terrain)
+                })
+                .collect()
+        };
+
+        rotated.translate(self.min_x() - rotated.min_x(),
+            self.min_y() - rotated.min_y())
+    }
+    /// Mirror the feature left to right within its bounding box.
+    pub fn mirror_horizontal(&self) -> Self {
+        let (min_x, max_x) = (self.min_x(), self.max_x());
+        Feature {
+            tiles: self.tiles.iter()
+                .map(|t| Tile::new(
+                    Location::new(min_x + max_x - t.loc.x, t.loc.y),
+                    t.terrain))
+                .collect()
+        }
+    }
+    /// Mirror the feature top to bottom within its bounding box.
+    pub fn mirror_vertical(&self) -> Self {
+        let (min_y, max_y) = (self.min_y(), self.max_y());
+        Feature {
+            tiles: self.tiles.iter()
+                .map(|t| Tile::new(
+                    Location::new(t.loc.x, min_y + max_y - t.loc.y),
+                    t.terrain))
+                .collect()
+        }
+    }
     pub fn place(&self, vert_align: VerticalAlignment,
         horiz_align: HorizontalAlignment, loc: Location) -> Self
     {
@@ -280,3 +317,68 @@ fn test_place_feature() {
             (5, 2) => Terrain::Wall
         ));
 }
+
+#[test]
+fn test_rotate_and_mirror_feature() {
+    // Set up a feature:
+    //
+    // ....
+    // .##.
+    // .#..
+    let feature = feature!(
+        (1, 1) => Terrain::Wall,
+        (2, 1) => Terrain::Wall,
+        (1, 2) => Terrain::Wall
+    );
+
+    // Rotate a quarter turn clockwise.
+    //
+    // ....
+    // .##.
+    // ..#.
+    let rotated = feature!(
+        (2, 1) => Terrain::Wall,
+        (2, 2) => Terrain::Wall,
+        (1, 1) => Terrain::Wall
+    );
+    assert_eq!(feature.rotate(1), rotated);
+    assert_eq!((rotated.min_x(), rotated.min_y()), (1, 1));
+    assert_eq!((rotated.max_x(), rotated.max_y()), (2, 2));
+
+    // Four quarter turns get back to where we started.
+    assert_eq!(feature.rotate(2), rotated.rotate(1));
+    assert_eq!(feature.rotate(3).rotate(1), feature);
+    assert_eq!(feature.rotate(4), feature);
+
+    // Mirror left to right.
+    //
+    // ....
+    // .##.
+    // ..#.
+    assert_eq!(feature.mirror_horizontal(),
+        feature!(
+            (2, 1) => Terrain::Wall,
+            (1, 1) => Terrain::Wall,
+            (2, 2) => Terrain::Wall
+        ));
+
+    // Mirror top to bottom.
+    //
+    // ....
+    // .#..
+    // .##.
+    assert_eq!(feature.mirror_vertical(),
+        feature!(
+            (1, 2) => Terrain::Wall,
+            (2, 2) => Terrain::Wall,
+            (1, 1) => Terrain::Wall
+        ));
+
+    // Vertical hallways are rotated horizontal ones.
+    assert_eq!(Feature::hallway(3, false),
+        feature!(
+            (0, 0) => Terrain::Floor,
+            (0, 1) => Terrain::Floor,
+            (0, 2) => Terrain::Floor
+        ));
+}
@@ -0,0 +1,202 @@
+use tile::Location;
+use world_map::WorldMap;
+use std::collections::HashSet;
+
+/// Compute the set of locations visible from origin within radius, using
+/// symmetric recursive shadowcasting: if a floor can see another floor,
+/// the other floor can see it back. Terrain that blocks sight is visible
+/// itself but hides whatever is behind it.
+pub fn field_of_view(world: &WorldMap, origin: Location, radius: i32)
+    -> HashSet<Location>
+{
+    let mut fov = FieldOfView {
+        world: world,
+        origin: origin,
+        radius: radius,
+        visible: HashSet::new()
+    };
+    fov.visible.insert(origin);
+    for &quadrant in [Quadrant::North, Quadrant::East, Quadrant::South,
+        Quadrant::West].iter() {
+        fov.scan(quadrant, Row {
+            depth: 1,
+            start_slope: Slope::new(-1, 1),
+            end_slope: Slope::new(1, 1)
+        });
+    }
+
+    fov.visible
+}
+
+// The four triangular sections of the view around the origin. Each is
+// scanned row by row moving away from the origin.
+#[derive(Copy, Clone)]
+enum Quadrant {
+    North,
+    East,
+    South,
+    West
+}
+
+impl Quadrant {
+    // Convert a (row, column) position within the quadrant into a map
+    // location.
+    fn transform(&self, origin: Location, depth: i32, col: i32) -> Location {
+        match *self {
+            Quadrant::North => Location::new(origin.x + col, origin.y - depth),
+            Quadrant::South => Location::new(origin.x + col, origin.y + depth),
+            Quadrant::East => Location::new(origin.x + depth, origin.y + col),
+            Quadrant::West => Location::new(origin.x - depth, origin.y + col)
+        }
+    }
+}
+
+// A slope as an exact fraction, to avoid rounding trouble at the edges
+// of shadows. The denominator is always positive.
+#[derive(Copy, Clone)]
+struct Slope {
+    num: i32,
+    den: i32
+}
+
+impl Slope {
+    fn new(num: i32, den: i32) -> Self {
+        Slope {num: num, den: den}
+    }
+    // The slope from the origin to the near edge of a cell.
+    fn of(depth: i32, col: i32) -> Self {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+}
+
+// Integer division rounding towards negative and positive infinity.
+fn div_floor(a: i32, b: i32) -> i32 {
+    let d = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) { d - 1 } else { d }
+}
+fn div_ceil(a: i32, b: i32) -> i32 {
+    let d = a / b;
+    if a % b != 0 && (a < 0) == (b < 0) { d + 1 } else { d }
+}
+
+// A row of cells within a quadrant, limited to the columns between two
+// slopes.
+#[derive(Copy, Clone)]
+struct Row {
+    depth: i32,
+    start_slope: Slope,
+    end_slope: Slope
+}
+
+impl Row {
+    fn min_col(&self) -> i32 {
+        // Round depth * start_slope, with ties going up.
+        let s = self.start_slope;
+        div_floor(2 * self.depth * s.num + s.den, 2 * s.den)
+    }
+    fn max_col(&self) -> i32 {
+        // Round depth * end_slope, with ties going down.
+        let s = self.end_slope;
+        div_ceil(2 * self.depth * s.num - s.den, 2 * s.den)
+    }
+    // Whether the center of a cell in the row lies within the row's slopes.
+    // Floors are only visible if this holds, which keeps things symmetric.
+    fn is_symmetric(&self, col: i32) -> bool {
+        let (s, e) = (self.start_slope, self.end_slope);
+        col * s.den >= self.depth * s.num && col * e.den <= self.depth * e.num
+    }
+    fn next(&self) -> Self {
+        Row {
+            depth: self.depth + 1,
+            start_slope: self.start_slope,
+            end_slope: self.end_slope
+        }
+    }
+}
+
+struct FieldOfView<'a> {
+    world: &'a WorldMap,
+    origin: Location,
+    radius: i32,
+    visible: HashSet<Location>
+}
+
+impl<'a> FieldOfView<'a> {
+    fn in_bounds(&self, loc: Location) -> bool {
+        loc.x >= 0 && loc.y >= 0 &&
+            loc.x < self.world.width() && loc.y < self.world.height()
+    }
+    // Cells off the edge of the map block sight.
+    fn blocks_sight(&self, loc: Location) -> bool {
+        !self.in_bounds(loc) || self.world.get_tile(loc).terrain.blocks_sight()
+    }
+    fn scan(&mut self, quadrant: Quadrant, mut row: Row) {
+        if row.depth > self.radius {
+            return;
+        }
+
+        let mut prev_blocks = None;
+        for col in row.min_col()..row.max_col() + 1 {
+            let loc = quadrant.transform(self.origin, row.depth, col);
+            let blocks = self.blocks_sight(loc);
+            if (blocks || row.is_symmetric(col)) && self.in_bounds(loc) &&
+                loc.euclidean(&self.origin) <= self.radius {
+                self.visible.insert(loc);
+            }
+            match (prev_blocks, blocks) {
+                // Coming out of a shadow: narrow the row to start here.
+                (Some(true), false) => {
+                    row.start_slope = Slope::of(row.depth, col);
+                },
+                // Going into a shadow: scan what can be seen past the
+                // cells so far.
+                (Some(false), true) => {
+                    let mut next_row = row.next();
+                    next_row.end_slope = Slope::of(row.depth, col);
+                    self.scan(quadrant, next_row);
+                },
+                _ => {}
+            }
+            prev_blocks = Some(blocks);
+        }
+        if prev_blocks == Some(false) {
+            self.scan(quadrant, row.next());
+        }
+    }
+}
+
+#[test]
+fn test_field_of_view() {
+    use config::GenerationConfig;
+    use tile::Terrain;
+
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        seed: 11,
+        ..Default::default()
+    };
+    let (world, start) = WorldMap::generate(&config);
+    let radius = 8;
+    let visible = field_of_view(&world, start, radius);
+    assert!(visible.contains(&start));
+    assert!(visible.iter().all(|l| l.euclidean(&start) <= radius));
+
+    // Floors that can see each other can do so both ways.
+    let floors: Vec<Location> = world.tiles()
+        .filter(|t| t.terrain == Terrain::Floor &&
+            t.loc.euclidean(&start) <= radius)
+        .map(|t| t.loc)
+        .collect();
+    let views: Vec<HashSet<Location>> = floors.iter()
+        .map(|l| field_of_view(&world, *l, radius))
+        .collect();
+    for (i, a) in floors.iter().enumerate() {
+        for (j, b) in floors.iter().enumerate() {
+            if views[i].contains(b) {
+                assert!(views[j].contains(a),
+                    "{:?} sees {:?} but not the other way around", a, b);
+            }
+        }
+    }
+}
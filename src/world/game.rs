@@ -0,0 +1,635 @@
+extern crate rand;
+
+use backend::{Backend, Color, Key, WHITE, BLACK};
+use camera::Camera;
+use combat::{attack, is_dead};
+use config::GenerationConfig;
+use cost::MoveCost;
+use dijkstra::Dijkstra;
+use dungeon::{Dungeon, Level};
+use ecs::{Ecs, Entity};
+use fov::field_of_view;
+use item::{pick_up, drop_item, use_item};
+use message::MessageLog;
+use monster::{Chase, take_monster_turn};
+use replay::Input;
+use save::SavedGame;
+use scheduler::{Scheduler, ACTION_COST};
+use tile::{Terrain, Location};
+use std::cmp;
+use std::collections::HashSet;
+use self::rand::{Rng, StdRng, SeedableRng};
+
+// Space taken off the screen for the sidebar and the message log, leaving
+// the rest for the map.
+pub const SIDEBAR_WIDTH: i32 = 20;
+pub const LOG_HEIGHT: i32 = 7;
+// The view scrolls once the player walks out of a box this size in the
+// middle of it.
+const CAMERA_DEADZONE: (i32, i32) = (20, 12);
+// How far the player can see.
+const PLAYER_RADIUS: i32 = 5;
+// Color of things remembered but out of view.
+const DIM: Color = (90, 90, 110);
+// Percent chance of forcing a locked door open each time it's bumped.
+const FORCE_DOOR_CHANCE: u32 = 25;
+
+/// Start a game from scratch.
+pub fn new_game(config: GenerationConfig) -> SavedGame {
+    let seed = config.seed;
+    let mut log = MessageLog::new();
+    log.add("Welcome to the dungeon! Press m to see older messages.", 0);
+
+    SavedGame {
+        dungeon: Dungeon::new(config),
+        scheduler: Scheduler::new(),
+        log: log,
+        seed: seed
+    }
+}
+
+/// A game being played, along with what the player can see of it.
+pub struct Game {
+    pub dungeon: Dungeon,
+    pub scheduler: Scheduler,
+    pub log: MessageLog,
+    rng: StdRng,
+    camera: Camera,
+    // Tiles of the current level worth exploring that haven't been seen.
+    unexplored: HashSet<Location>,
+    visible: HashSet<Location>
+}
+
+impl Game {
+    /// Start playing a game on a screen of the given size.
+    pub fn new(saved: SavedGame, screen: (i32, i32)) -> Self {
+        let SavedGame { dungeon, scheduler, log, seed } = saved;
+        let unexplored = find_unexplored(dungeon.level());
+        let mut game = Game {
+            dungeon: dungeon,
+            scheduler: scheduler,
+            log: log,
+            rng: StdRng::from_seed(&[seed][..]),
+            camera: Camera::new(screen.0 - SIDEBAR_WIDTH,
+                screen.1 - LOG_HEIGHT, CAMERA_DEADZONE),
+            unexplored: unexplored,
+            visible: HashSet::new()
+        };
+        {
+            let Game { ref mut dungeon, ref mut scheduler, ref mut log,
+                ref mut rng, ref mut unexplored, ref mut visible, .. } = game;
+            let (player, player_loc) =
+                (dungeon.player(), dungeon.player_loc());
+            let level = dungeon.level_mut();
+            run_until_player(rng, scheduler, level, player, log);
+            *visible = explore(level, player_loc, PLAYER_RADIUS, unexplored);
+        }
+
+        game
+    }
+    /// Whether the player has died.
+    pub fn is_over(&self) -> bool {
+        is_dead(&self.dungeon.level().entities, self.dungeon.player())
+    }
+    /// Stop playing, keeping what's needed to carry on later.
+    pub fn into_saved(mut self) -> SavedGame {
+        let seed = self.rng.gen::<usize>();
+        SavedGame {
+            dungeon: self.dungeon,
+            scheduler: self.scheduler,
+            log: self.log,
+            seed: seed
+        }
+    }
+    /// Play until the player quits or dies, or the input runs out.
+    pub fn run<B: Backend>(&mut self, backend: &mut B, input: &mut Input) {
+        let Game { ref mut dungeon, ref mut scheduler, ref mut log,
+            ref mut rng, ref mut camera, ref mut unexplored,
+            ref mut visible } = *self;
+
+        loop {
+            if input.take_save() {
+                // Saving reseeds the random number generator from itself.
+                *rng = StdRng::from_seed(&[rng.gen::<usize>()][..]);
+                log.add("Welcome back!", scheduler.time());
+            }
+
+            let player = dungeon.player();
+            if is_dead(&dungeon.level().entities, player) {
+                log.add("You die... Press any key to quit.", scheduler.time());
+                draw(backend, dungeon.level(), camera, visible, player,
+                    log, scheduler.time());
+                input.next_key(backend);
+                break;
+            }
+
+            // Draw world.
+            let mut player_loc = dungeon.player_loc();
+            draw(backend, dungeon.level(), camera, visible, player,
+                log, scheduler.time());
+
+            // Act on the next key.
+            let new_loc = match input.next_key(backend) {
+                // In a replay, quitting and picking the game back up carries on
+                // from where it was.
+                Some(Key::Escape) if input.save_follows() => continue,
+                None | Some(Key::Escape) => break,
+                Some(Key::Up) => {
+                    if player_loc.y > 0 {
+                        Location::new(player_loc.x, player_loc.y - 1)
+                    } else { player_loc }
+                },
+                Some(Key::Down) => Location::new(player_loc.x, player_loc.y + 1),
+                Some(Key::Left) => {
+                    if player_loc.x > 0 {
+                        Location::new(player_loc.x - 1, player_loc.y)
+                    } else { player_loc }
+                },
+                Some(Key::Right) => Location::new(player_loc.x + 1, player_loc.y),
+                Some(Key::Char('y')) => {
+                    if player_loc.x > 0 && player_loc.y > 0 {
+                        Location::new(player_loc.x - 1, player_loc.y - 1)
+                    } else { player_loc }
+                },
+                Some(Key::Char('u')) => {
+                    if player_loc.y > 0 {
+                        Location::new(player_loc.x + 1, player_loc.y - 1)
+                    } else { player_loc }
+                },
+                Some(Key::Char('b')) => {
+                    if player_loc.x > 0 {
+                        Location::new(player_loc.x - 1, player_loc.y + 1)
+                    } else { player_loc }
+                },
+                Some(Key::Char('n')) => {
+                    Location::new(player_loc.x + 1, player_loc.y + 1)
+                },
+                Some(Key::Char('>')) => {
+                    let on_stairs = dungeon.level().world
+                        .get_tile(player_loc).terrain == Terrain::StairsDown;
+                    if on_stairs {
+                        player_loc = dungeon.descend();
+                        *unexplored = find_unexplored(dungeon.level());
+                        log.add(format!("You go down to depth {}.",
+                            dungeon.depth()), scheduler.time());
+                        player_loc
+                    } else {
+                        let level = dungeon.level_mut();
+                        if !level.explored.contains(&level.down_stairs) {
+                            continue;
+                        }
+                        // Travel to the down stairs once they've been seen.
+                        let path = level.world.find_path(player_loc,
+                            level.down_stairs, Location::chebyshev, &MoveCost::terrain());
+                        for step in path.unwrap_or(Vec::new()) {
+                            if level.world.get_tile(step).terrain == Terrain::DoorClosed {
+                                level.world.get_tile_mut(step).terrain = Terrain::DoorOpen;
+                            }
+                            if level.entities.is_blocked(step) {
+                                break;
+                            }
+                            player_loc = step;
+                            level.entities.set_position(player, player_loc);
+                            *visible = explore(level, player_loc, PLAYER_RADIUS, unexplored);
+                            end_turn(rng, scheduler, level, player, log);
+                            draw(backend, level, camera, visible, player, log, scheduler.time());
+                            if monster_in_view(&level.entities, visible) ||
+                                is_dead(&level.entities, player) {
+                                break;
+                            }
+                        }
+                        // Each step took a turn of its own.
+                        continue;
+                    }
+                },
+                Some(Key::Char('g')) => {
+                    let level = dungeon.level_mut();
+                    let item = level.entities.entities_at(player_loc).iter()
+                        .cloned()
+                        .find(|e| level.entities.items.contains(*e));
+                    let item = match item {
+                        Some(item) => item,
+                        None => {
+                            log.add("There is nothing here to pick up.",
+                                scheduler.time());
+                            continue;
+                        }
+                    };
+                    let name = level.entities.names.get(item).cloned()
+                        .unwrap_or(String::new());
+                    if pick_up(&mut level.entities, player, item) {
+                        log.add(format!("You pick up the {}.", name),
+                            scheduler.time());
+                    } else {
+                        log.add(format!("You have no room for the {}.", name),
+                            scheduler.time());
+                        continue;
+                    }
+                    player_loc
+                },
+                Some(Key::Char('i')) => {
+                    match choose_item(backend, input,
+                        &dungeon.level().entities,
+                        player, "Use which item?") {
+                        Some(item) => {
+                            let entities = &mut dungeon.level_mut().entities;
+                            let name = entities.names.get(item).cloned()
+                                .unwrap_or(String::new());
+                            use_item(entities, player, item);
+                            log.add(format!("You use the {}.", name),
+                                scheduler.time());
+                        },
+                        None => continue
+                    }
+                    player_loc
+                },
+                Some(Key::Char('d')) => {
+                    match choose_item(backend, input,
+                        &dungeon.level().entities,
+                        player, "Drop which item?") {
+                        Some(item) => {
+                            let entities = &mut dungeon.level_mut().entities;
+                            let name = entities.names.get(item).cloned()
+                                .unwrap_or(String::new());
+                            drop_item(entities, player, item);
+                            log.add(format!("You drop the {}.", name),
+                                scheduler.time());
+                        },
+                        None => continue
+                    }
+                    player_loc
+                },
+                Some(Key::Char('<')) => {
+                    let world = &dungeon.level().world;
+                    if world.get_tile(player_loc).terrain != Terrain::StairsUp {
+                        continue;
+                    }
+                    match dungeon.ascend() {
+                        Some(loc) => {
+                            *unexplored = find_unexplored(dungeon.level());
+                            log.add(format!("You go up to depth {}.",
+                                dungeon.depth()), scheduler.time());
+                            loc
+                        },
+                        None => continue
+                    }
+                },
+                Some(Key::Char('m')) => {
+                    show_history(backend, input, log);
+                    continue;
+                },
+                Some(Key::Char('o')) if !unexplored.is_empty() => {
+                    let level = dungeon.level_mut();
+                    let goals: Vec<Location> = unexplored.iter().map(|l| *l).collect();
+                    // The map is kept up to date as things get explored
+                    // rather than rebuilt every step.
+                    let mut dmap = Dijkstra::with_cost(&level.world, goals, 1000,
+                        MoveCost::terrain());
+                    while !unexplored.is_empty() {
+                        let possible: Vec<Location> = dmap
+                            .sorted_neighbors(&player_loc).iter()
+                            .filter(|l| level.world.get_tile(**l).terrain.is_passable())
+                            .map(|l| *l)
+                            .collect();
+                        if possible.is_empty() {
+                            // Everything left is out of reach.
+                            break;
+                        }
+                        if level.world.get_tile(possible[0]).terrain == Terrain::DoorClosed {
+                            level.world.get_tile_mut(possible[0]).terrain = Terrain::DoorOpen;
+                            dmap.invalidate(&level.world, &[possible[0]]);
+                        } else if level.entities.is_blocked(possible[0]) {
+                            break;
+                        } else {
+                            player_loc = possible[0];
+                            level.entities.set_position(player, player_loc);
+                        }
+                        *visible = explore(level, player_loc, PLAYER_RADIUS, unexplored);
+                        // Whatever just came into view is no longer worth
+                        // heading for.
+                        let seen: Vec<Location> = visible.iter().map(|l| *l).collect();
+                        dmap.remove_goals(&level.world, &seen);
+                        end_turn(rng, scheduler, level, player, log);
+                        draw(backend, level, camera, visible, player, log, scheduler.time());
+                        if monster_in_view(&level.entities, visible) ||
+                            is_dead(&level.entities, player) {
+                            break;
+                        }
+                    }
+                    // Each step took a turn of its own.
+                    continue;
+                }
+                _ => continue
+            };
+            // The player may have changed levels.
+            let player = dungeon.player();
+            let level = dungeon.level_mut();
+            // Bumping into something with health attacks it.
+            let target = level.entities.entities_at(new_loc).iter()
+                .cloned()
+                .find(|e| *e != player && level.entities.healths.contains(*e));
+            match level.world.get_tile(new_loc).terrain {
+                _ if target.is_some() => {
+                    let result = attack(rng, &mut level.entities, player,
+                        target.unwrap());
+                    log.add(result.describe(), scheduler.time());
+                },
+                // Bumping into a closed door opens it.
+                Terrain::DoorClosed => {
+                    level.world.get_tile_mut(new_loc).terrain =
+                        Terrain::DoorOpen;
+                },
+                // Locked doors take a few goes to force open.
+                Terrain::DoorLocked => {
+                    if rng.gen_range::<u32>(0, 100) < FORCE_DOOR_CHANCE {
+                        level.world.get_tile_mut(new_loc).terrain =
+                            Terrain::DoorOpen;
+                        log.add("You force the door open.", scheduler.time());
+                    } else {
+                        log.add("The door is locked. You try to force it.",
+                            scheduler.time());
+                    }
+                },
+                t if t.is_passable() && !level.entities.is_blocked(new_loc) => {
+                    level.entities.set_position(player, new_loc);
+                },
+                _ => {}
+            }
+            end_turn(rng, scheduler, level, player, log);
+            let player_loc = level.entities.position(player).unwrap();
+            *visible = explore(level, player_loc, PLAYER_RADIUS,
+                unexplored);
+        }
+    }
+}
+
+// Mark everything in view of a location as explored and remember the items
+// lying there, returning the locations in view.
+fn explore(level: &mut Level, loc: Location, radius: i32,
+    unexplored: &mut HashSet<Location>) -> HashSet<Location> {
+    let visible = field_of_view(&level.world, loc, radius);
+    for visible_loc in visible.iter() {
+        level.explored.insert(*visible_loc);
+        unexplored.remove(visible_loc);
+        let item = level.entities.entities_at(*visible_loc).iter()
+            .filter(|e| level.entities.items.contains(**e))
+            .filter_map(|e| level.entities.renderables.get(*e))
+            .last()
+            .cloned();
+        match item {
+            Some(r) => level.remembered_items.insert(*visible_loc, r),
+            None => level.remembered_items.remove(visible_loc)
+        };
+    }
+
+    visible
+}
+
+// Find the tiles of a level that are worth exploring but haven't been seen.
+fn find_unexplored(level: &Level) -> HashSet<Location> {
+    level.world.tiles()
+        .filter(|t| t.terrain != Terrain::Nothing)
+        .filter(|t| !level.explored.contains(&t.loc))
+        .map(|t| t.loc)
+        .collect()
+}
+
+// Let everything else on the level act until it's the player's turn again.
+fn run_until_player<R: Rng>(rng: &mut R, scheduler: &mut Scheduler,
+    level: &mut Level, player: Entity, log: &mut MessageLog) {
+    // The player can't move until then, so every monster chases them the
+    // same way. Work it out when the first one acts.
+    let mut chase = None;
+    loop {
+        let actor = scheduler.next_actor(&mut level.entities);
+        if actor == player || is_dead(&level.entities, player) {
+            return;
+        }
+        let chase = chase.get_or_insert_with(|| {
+            let sight = level.entities.monsters.iter()
+                .map(|(_, m)| m.sight)
+                .max()
+                .unwrap_or(0);
+            Chase::new(&level.world, level.entities.position(player).unwrap(),
+                sight)
+        });
+        let result = take_monster_turn(rng, &mut level.world,
+            &mut level.entities, chase, actor, player);
+        if let Some(result) = result {
+            log.add(result.describe(), scheduler.time());
+        }
+        scheduler.spend(&mut level.entities, actor, ACTION_COST);
+    }
+}
+
+// Finish the player's turn after they've done something.
+fn end_turn<R: Rng>(rng: &mut R, scheduler: &mut Scheduler,
+    level: &mut Level, player: Entity, log: &mut MessageLog) {
+    scheduler.spend(&mut level.entities, player, ACTION_COST);
+    run_until_player(rng, scheduler, level, player, log);
+}
+
+// Whether any monsters can be seen, which interrupts travel and
+// autoexplore.
+fn monster_in_view(entities: &Ecs, visible: &HashSet<Location>) -> bool {
+    entities.monsters.iter()
+        .filter_map(|(e, _)| entities.position(e))
+        .any(|l| visible.contains(&l))
+}
+
+// Show the player's inventory over the map and wait for them to pick an
+// item by its letter. Returns None if they pick anything else.
+fn choose_item<B: Backend>(backend: &mut B, input: &mut Input,
+    entities: &Ecs, player: Entity, title: &str) -> Option<Entity> {
+    let items = entities.inventories.get(player)
+        .map_or(Vec::new(), |i| i.items.clone());
+    backend.clear_area(0, 0, 40, items.len() as i32 + 5);
+    backend.print(1, 1, title);
+    if items.is_empty() {
+        backend.print(1, 3, "(empty)");
+    }
+    for (i, item) in items.iter().enumerate() {
+        let name = entities.names.get(*item).map_or("?", |n| &n[..]);
+        backend.print(1, 3 + i as i32,
+            &format!("{}) {}", (b'a' + i as u8) as char, name));
+    }
+    backend.flush();
+
+    match input.next_key(backend) {
+        Some(Key::Char(c)) if c >= 'a' && c <= 'z' => {
+            items.get(c as usize - 'a' as usize).cloned()
+        },
+        _ => None
+    }
+}
+
+// Show every message so far, scrolling with the arrow and page keys, until
+// any other key is pressed.
+fn show_history<B: Backend>(backend: &mut B, input: &mut Input,
+    log: &MessageLog) {
+    let messages = log.messages();
+    let (width, height) = backend.size();
+    let page = height - 3;
+    let last_top = cmp::max(0, messages.len() as i32 - page);
+    let mut top = last_top;
+    loop {
+        backend.clear_area(0, 0, width, height);
+        backend.print(1, 0, "Message history (arrows to scroll)");
+        for (i, message) in messages.iter().skip(top as usize)
+            .take(page as usize).enumerate() {
+            backend.print(1, 2 + i as i32, &message.to_string());
+        }
+        backend.flush();
+
+        top = match input.next_key(backend) {
+            Some(Key::Up) => top - 1,
+            Some(Key::Down) => top + 1,
+            Some(Key::PageUp) => top - page,
+            Some(Key::PageDown) => top + page,
+            _ => return
+        };
+        top = cmp::max(0, cmp::min(last_top, top));
+    }
+}
+
+// Draw the part of the map around the player with their stats to its right
+// and the most recent messages below it.
+fn draw<B: Backend>(backend: &mut B, level: &Level,
+    camera: &mut Camera, visible: &HashSet<Location>, player: Entity,
+    log: &MessageLog, time: u64) {
+    if let Some(loc) = level.entities.position(player) {
+        let (width, height) = (level.world.width(), level.world.height());
+        // Jump straight to the player if they've left the view, like after
+        // taking the stairs.
+        if camera.to_screen(loc).is_some() {
+            camera.follow(loc, width, height);
+        } else {
+            camera.center_on(loc, width, height);
+        }
+    }
+    draw_world(backend, level, camera, visible);
+
+    let (x, y) = (camera.width, camera.height);
+    backend.clear_area(x, 0, SIDEBAR_WIDTH, y);
+    let entities = &level.entities;
+    if let Some(health) = entities.healths.get(player) {
+        backend.print(x + 1, 1,
+            &format!("HP: {}/{}", health.current, health.max));
+    }
+    if let Some(stats) = entities.combat.get(player) {
+        backend.print(x + 1, 2, &format!("Attack: {}", stats.attack));
+        backend.print(x + 1, 3, &format!("Defense: {}", stats.defense));
+    }
+    backend.print(x + 1, 5, &format!("Depth: {}", level.depth));
+    backend.print(x + 1, 6, &format!("Turn: {}", time));
+
+    backend.clear_area(0, y, x + SIDEBAR_WIDTH, LOG_HEIGHT);
+    for (i, message) in log.recent(LOG_HEIGHT as usize).iter().enumerate() {
+        backend.print(1, y + i as i32, &message.to_string());
+    }
+    backend.flush();
+}
+
+fn draw_world<B: Backend>(backend: &mut B, level: &Level,
+    camera: &Camera, visible: &HashSet<Location>) {
+    let (world, explored, entities) =
+        (&level.world, &level.explored, &level.entities);
+    for y in 0..camera.height {
+        for x in 0..camera.width {
+            let loc = camera.to_world(x, y);
+            if loc.x >= world.width() || loc.y >= world.height() {
+                // The map is smaller than the view.
+                backend.put(x, y, ' ', WHITE, BLACK);
+                continue;
+            }
+            let tile = world.get_tile(loc);
+            let ascii = match (tile.terrain, explored.contains(&tile.loc)) {
+                (Terrain::Floor, true) => '.',
+                (Terrain::Wall, true) => '#',
+                (Terrain::StairsUp, true) => '<',
+                (Terrain::StairsDown, true) => '>',
+                (Terrain::DoorClosed, true) |
+                    (Terrain::DoorLocked, true) => '+',
+                (Terrain::DoorOpen, true) => '\'',
+                (Terrain::Rubble, true) => ':',
+                (Terrain::ShallowWater, true) => '~',
+                (Terrain::Debug, true) => 'X',
+                _ => ' '
+            };
+            // Tiles out of view are drawn as they were remembered, dimmed.
+            let color = if visible.contains(&tile.loc) { WHITE } else { DIM };
+            backend.put(x, y, ascii, color, BLACK);
+        }
+    }
+
+    // Items out of view are drawn where they were last seen.
+    for (loc, r) in level.remembered_items.iter() {
+        match camera.to_screen(*loc) {
+            Some((x, y)) if !visible.contains(loc) => {
+                backend.put(x, y, r.glyph, DIM, BLACK);
+            },
+            _ => {}
+        }
+    }
+
+    // Other entities are only drawn while in view, on top of any items.
+    let in_view = entities.positions().join(&entities.renderables);
+    let (items, others): (Vec<_>, Vec<_>) = in_view.into_iter()
+        .filter(|&(_, loc, _)| visible.contains(loc))
+        .partition(|&(e, _, _)| entities.items.contains(e));
+    for (_, loc, r) in items.into_iter().chain(others.into_iter()) {
+        if let Some((x, y)) = camera.to_screen(*loc) {
+            backend.put(x, y, r.glyph, r.color, BLACK);
+        }
+    }
+}
+
+
+#[test]
+fn test_game_runs_headless() {
+    use backend::HeadlessBackend;
+    use replay::{Recorder, Replay};
+
+    let config = GenerationConfig {
+        width: 80,
+        height: 50,
+        seed: 4,
+        ..Default::default()
+    };
+    let keys = vec![Key::Char('o'), Key::Char('m'), Key::Escape, Key::Right,
+        Key::Char('o'), Key::Left, Key::Char('i'), Key::Escape];
+    let path = ::std::env::temp_dir()
+        .join(format!("roguelike-game-{}.replay", ::std::process::id()));
+
+    // Play until the keys run out, recording them.
+    let mut backend = HeadlessBackend::new(80, 50, keys);
+    let mut game = Game::new(new_game(config.clone()), backend.size());
+    let start = game.dungeon.player_loc();
+    let mut input = Input::new(Some(Recorder::create(&path, 4).unwrap()));
+    game.run(&mut backend, &mut input);
+    assert!(game.scheduler.time() > 0);
+    assert!(game.is_over() || game.dungeon.player_loc() != start);
+    let screen = backend.text();
+    assert!(screen.contains("HP: "));
+    assert!(screen.contains("Depth: 1"));
+    assert!(screen.contains("Welcome to the dungeon!"));
+
+    // Replaying the keys plays out the same way.
+    let replay = Replay::load(&path).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+    assert_eq!(replay.seed, 4);
+    let mut replayed = HeadlessBackend::new(80, 50, Vec::new());
+    let mut game = Game::new(new_game(config.clone()), replayed.size());
+    let mut input = Input::from_replay(replay,
+        ::std::time::Duration::from_millis(0));
+    game.run(&mut replayed, &mut input);
+    assert_eq!(replayed.text(), screen);
+
+    // Stairs keys away from the stairs don't take a turn.
+    let keys = vec![Key::Char('<'), Key::Char('>')];
+    let mut backend = HeadlessBackend::new(80, 50, keys);
+    let mut game = Game::new(new_game(config), backend.size());
+    let time = game.scheduler.time();
+    game.run(&mut backend, &mut Input::new(None));
+    assert_eq!(game.scheduler.time(), time);
+}
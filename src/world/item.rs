@@ -0,0 +1,180 @@
+extern crate rand;
+
+use ecs::{Ecs, Entity, Renderable, Item, ItemEffect};
+use random::RandomTable;
+use tile::Location;
+use std::cmp;
+use self::rand::Rng;
+
+/// A type of item that can be spawned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ItemKind {
+    pub name: &'static str,
+    pub glyph: char,
+    pub color: (u8, u8, u8),
+    pub effect: ItemEffect
+}
+
+pub const HEALING_POTION: ItemKind = ItemKind {
+    name: "healing potion", glyph: '!', color: (220, 60, 90),
+    effect: ItemEffect::Heal(10)
+};
+pub const WHETSTONE: ItemKind = ItemKind {
+    name: "whetstone", glyph: '*', color: (170, 170, 190),
+    effect: ItemEffect::RaiseAttack(1)
+};
+pub const IRONBARK_SALVE: ItemKind = ItemKind {
+    name: "ironbark salve", glyph: '!', color: (140, 100, 50),
+    effect: ItemEffect::RaiseDefense(1)
+};
+
+/// Build the table of items that can appear at a depth.
+pub fn item_table<R: Rng + 'static>(depth: u32) -> RandomTable<ItemKind, R> {
+    let mut entries: Vec<(Box<Fn(&mut R) -> ItemKind>, u32)> = vec![
+        (Box::new(|_| HEALING_POTION), 6),
+        (Box::new(|_| WHETSTONE), 1)
+    ];
+    if depth >= 2 {
+        entries.push((Box::new(|_| IRONBARK_SALVE), 1));
+    }
+
+    RandomTable::new(entries)
+}
+
+/// Create an item of the given kind lying at a location.
+pub fn spawn_item(ecs: &mut Ecs, kind: &ItemKind, loc: Location) -> Entity {
+    let item = ecs.create();
+    ecs.renderables.insert(item,
+        Renderable { glyph: kind.glyph, color: kind.color });
+    ecs.names.insert(item, kind.name.to_string());
+    ecs.items.insert(item, Item { effect: kind.effect });
+    ecs.set_position(item, loc);
+
+    item
+}
+
+/// Move an item from the floor into an entity's inventory. Returns false,
+/// leaving the item where it is, if it isn't an item lying where the entity
+/// is or there's no room for it.
+pub fn pick_up(ecs: &mut Ecs, who: Entity, item: Entity) -> bool {
+    let here = ecs.position(who);
+    if !ecs.items.contains(item) || here.is_none() ||
+        ecs.position(item) != here {
+        return false;
+    }
+    match ecs.inventories.get_mut(who) {
+        Some(inventory) if inventory.items.len() < inventory.capacity => {
+            inventory.items.push(item);
+        },
+        _ => return false
+    }
+    ecs.remove_position(item);
+
+    true
+}
+
+/// Put an item from an entity's inventory down where the entity is.
+/// Returns false if the entity isn't carrying it.
+pub fn drop_item(ecs: &mut Ecs, who: Entity, item: Entity) -> bool {
+    let loc = match (take_from_inventory(ecs, who, item), ecs.position(who)) {
+        (true, Some(loc)) => loc,
+        _ => return false
+    };
+    ecs.set_position(item, loc);
+
+    true
+}
+
+/// Use up an item from an entity's inventory, applying its effect to the
+/// entity. Returns false if the entity isn't carrying it.
+pub fn use_item(ecs: &mut Ecs, who: Entity, item: Entity) -> bool {
+    let effect = match ecs.items.get(item) {
+        Some(i) => i.effect,
+        None => return false
+    };
+    if !take_from_inventory(ecs, who, item) {
+        return false;
+    }
+
+    match effect {
+        ItemEffect::Heal(amount) => {
+            if let Some(health) = ecs.healths.get_mut(who) {
+                health.current = cmp::min(health.max, health.current + amount);
+            }
+        },
+        ItemEffect::RaiseAttack(amount) => {
+            if let Some(stats) = ecs.combat.get_mut(who) {
+                stats.attack += amount;
+            }
+        },
+        ItemEffect::RaiseDefense(amount) => {
+            if let Some(stats) = ecs.combat.get_mut(who) {
+                stats.defense += amount;
+            }
+        }
+    }
+    ecs.destroy(item);
+
+    true
+}
+
+fn take_from_inventory(ecs: &mut Ecs, who: Entity, item: Entity) -> bool {
+    match ecs.inventories.get_mut(who) {
+        Some(inventory) => {
+            let before = inventory.items.len();
+            inventory.items.retain(|i| *i != item);
+            inventory.items.len() < before
+        },
+        None => false
+    }
+}
+
+#[test]
+fn test_inventory() {
+    use ecs::{Health, Inventory};
+
+    let mut ecs = Ecs::new();
+    let here = Location::new(2, 2);
+    let player = ecs.create();
+    ecs.healths.insert(player, Health { current: 3, max: 10 });
+    ecs.inventories.insert(player, Inventory { items: Vec::new(), capacity: 1 });
+    ecs.set_position(player, here);
+    let potion = spawn_item(&mut ecs, &HEALING_POTION, here);
+    let stone = spawn_item(&mut ecs, &WHETSTONE, here);
+
+    // Picking things up takes them off the floor, while there's room.
+    assert!(!pick_up(&mut ecs, player, player));
+    assert!(pick_up(&mut ecs, player, potion));
+    assert!(!pick_up(&mut ecs, player, stone));
+    assert_eq!(ecs.entities_at(here), &[player, stone]);
+
+    // Only things lying on the same spot can be picked up, so nothing gets
+    // taken out of someone else's hands.
+    let other_player = ecs.create();
+    ecs.inventories.insert(other_player,
+        Inventory { items: Vec::new(), capacity: 5 });
+    ecs.set_position(other_player, here);
+    assert!(!pick_up(&mut ecs, other_player, potion));
+    ecs.set_position(other_player, Location::new(3, 2));
+    assert!(!pick_up(&mut ecs, other_player, stone));
+    ecs.destroy(other_player);
+
+    // Inventories come along when changing levels.
+    let mut other = Ecs::new();
+    let player = ecs.transfer(player, &mut other);
+    assert!(!ecs.is_alive(potion));
+    let potion = other.inventories.get(player).unwrap().items[0];
+    assert_eq!(other.names.get(potion).unwrap(), "healing potion");
+    let mut ecs = other;
+    ecs.set_position(player, here);
+
+    // Dropping puts it back down; using it heals and uses it up.
+    assert!(drop_item(&mut ecs, player, potion));
+    assert!(!drop_item(&mut ecs, player, potion));
+    assert_eq!(ecs.entities_at(here), &[player, potion]);
+    assert!(pick_up(&mut ecs, player, potion));
+    assert!(use_item(&mut ecs, player, potion));
+    assert!(!ecs.is_alive(potion));
+    assert_eq!(ecs.healths.get(player).unwrap().current, 10);
+    assert!(ecs.inventories.get(player).unwrap().items.is_empty());
+}
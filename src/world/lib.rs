@@ -2,7 +2,48 @@ mod world_map;
 mod random;
 mod feature;
 mod tile;
+mod config;
+mod bsp;
+mod vault;
+mod dungeon;
+mod fov;
+mod astar;
+mod dijkstra;
+mod cost;
+mod ecs;
+mod monster;
+mod combat;
+mod item;
+mod scheduler;
+mod message;
+mod camera;
+mod save;
+mod backend;
+mod replay;
+mod game;
+#[cfg(feature = "terminal")]
+mod terminal;
 pub use world_map::*;
 pub use random::*;
 pub use feature::*;
 pub use tile::*;
+pub use config::*;
+pub use bsp::*;
+pub use vault::*;
+pub use dungeon::*;
+pub use fov::*;
+pub use dijkstra::*;
+pub use cost::*;
+pub use ecs::*;
+pub use monster::*;
+pub use combat::*;
+pub use item::*;
+pub use scheduler::*;
+pub use message::*;
+pub use camera::*;
+pub use save::*;
+pub use backend::*;
+pub use replay::*;
+pub use game::*;
+#[cfg(feature = "terminal")]
+pub use terminal::*;
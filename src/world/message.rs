@@ -0,0 +1,93 @@
+use save::{Save, Load, SaveWriter, SaveReader, SaveError};
+use std::fmt;
+
+/// A line in the message log. Repeats of the same message in a row are
+/// counted instead of being added again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub text: String,
+    pub count: u32,
+    // Game time when the message was last added.
+    pub time: u64
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count > 1 {
+            write!(f, "{} (x{})", self.text, self.count)
+        } else {
+            write!(f, "{}", self.text)
+        }
+    }
+}
+
+/// Everything that has been reported to the player, oldest first.
+pub struct MessageLog {
+    messages: Vec<Message>
+}
+
+impl MessageLog {
+    pub fn new() -> Self {
+        MessageLog {messages: Vec::new()}
+    }
+    pub fn add<S: Into<String>>(&mut self, text: S, time: u64) {
+        let text = text.into();
+        if let Some(last) = self.messages.last_mut() {
+            if last.text == text {
+                last.count += 1;
+                last.time = time;
+                return;
+            }
+        }
+        self.messages.push(Message {text: text, count: 1, time: time});
+    }
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+    /// Return up to the last count messages, oldest first.
+    pub fn recent(&self, count: usize) -> &[Message] {
+        let start = self.messages.len().saturating_sub(count);
+        &self.messages[start..]
+    }
+}
+
+impl Save for MessageLog {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.messages.len());
+        for message in self.messages.iter() {
+            w.string(&message.text);
+            w.token(message.count);
+            w.token(message.time);
+        }
+    }
+}
+
+impl Load for MessageLog {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let messages: Vec<(String, u32, u64)> = try!(Load::load(r));
+        Ok(MessageLog {
+            messages: messages.into_iter()
+                .map(|(text, count, time)|
+                    Message {text: text, count: count, time: time})
+                .collect()
+        })
+    }
+}
+
+#[test]
+fn test_message_log() {
+    let mut log = MessageLog::new();
+    log.add("You hit the rat.", 1);
+    log.add("You hit the rat.", 2);
+    log.add("You hit the rat.", 3);
+    log.add("The rat bites you.", 3);
+    log.add("You hit the rat.", 4);
+    let lines: Vec<String> = log.messages().iter()
+        .map(|m| m.to_string())
+        .collect();
+    assert_eq!(lines, vec!["You hit the rat. (x3)", "The rat bites you.",
+        "You hit the rat."]);
+    assert_eq!(log.messages()[0].time, 3);
+    assert_eq!(log.recent(2).len(), 2);
+    assert_eq!(log.recent(10).len(), 3);
+}
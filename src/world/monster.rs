@@ -0,0 +1,224 @@
+extern crate rand;
+
+use dijkstra::Dijkstra;
+use combat::{attack, is_dead, AttackResult};
+use ecs::{Ecs, Entity, Renderable, Health, CombatStats, BlocksMovement,
+    Monster, Actor};
+use fov::field_of_view;
+use random::RandomTable;
+use tile::{Terrain, Location};
+use world_map::WorldMap;
+use std::collections::HashSet;
+use self::rand::Rng;
+
+/// A type of monster that can be spawned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MonsterKind {
+    pub name: &'static str,
+    pub glyph: char,
+    pub color: (u8, u8, u8),
+    pub health: i32,
+    pub attack: i32,
+    pub defense: i32,
+    // How far away the monster can see the player from.
+    pub sight: i32,
+    // How quickly the monster acts compared to the player, who has a speed
+    // of 100.
+    pub speed: i32
+}
+
+pub const RAT: MonsterKind = MonsterKind {
+    name: "rat", glyph: 'r', color: (150, 110, 70), health: 4, attack: 2,
+    defense: 0, sight: 6, speed: 120
+};
+pub const GOBLIN: MonsterKind = MonsterKind {
+    name: "goblin", glyph: 'g', color: (90, 170, 60), health: 8, attack: 4,
+    defense: 1, sight: 8, speed: 100
+};
+pub const ORC: MonsterKind = MonsterKind {
+    name: "orc", glyph: 'o', color: (60, 120, 40), health: 14, attack: 6,
+    defense: 2, sight: 8, speed: 100
+};
+pub const TROLL: MonsterKind = MonsterKind {
+    name: "troll", glyph: 'T', color: (120, 160, 120), health: 24,
+    attack: 9, defense: 3, sight: 7, speed: 70
+};
+
+/// Build the table of monsters that can appear at a depth. Tougher
+/// monsters only show up further down.
+pub fn monster_table<R: Rng + 'static>(depth: u32)
+    -> RandomTable<MonsterKind, R>
+{
+    let mut entries: Vec<(Box<Fn(&mut R) -> MonsterKind>, u32)> = vec![
+        (Box::new(|_| RAT), 4),
+        (Box::new(|_| GOBLIN), 3)
+    ];
+    if depth >= 2 {
+        entries.push((Box::new(|_| ORC), 2));
+    }
+    if depth >= 4 {
+        entries.push((Box::new(|_| TROLL), 1));
+    }
+
+    RandomTable::new(entries)
+}
+
+/// Create a monster of the given kind at a location.
+pub fn spawn_monster(ecs: &mut Ecs, kind: &MonsterKind, loc: Location)
+    -> Entity
+{
+    let monster = ecs.create();
+    ecs.renderables.insert(monster,
+        Renderable { glyph: kind.glyph, color: kind.color });
+    ecs.names.insert(monster, kind.name.to_string());
+    ecs.healths.insert(monster,
+        Health { current: kind.health, max: kind.health });
+    ecs.combat.insert(monster,
+        CombatStats { attack: kind.attack, defense: kind.defense });
+    ecs.blockers.insert(monster, BlocksMovement);
+    ecs.monsters.insert(monster, Monster { sight: kind.sight });
+    ecs.actors.insert(monster, Actor { speed: kind.speed, energy: 0 });
+    ecs.set_position(monster, loc);
+
+    monster
+}
+
+/// What monsters chasing a target know about it: where it can be seen from
+/// and how to get to it. The target has to stay put while this is in use,
+/// so it's worked out once per turn of the target's and shared by every
+/// monster that acts before it moves again.
+pub struct Chase {
+    target: Location,
+    // Locations within the longest sight of any monster that can see the
+    // target, which works because field of view is symmetric. Doors opened
+    // in the meantime only count once this is worked out again.
+    visible: HashSet<Location>,
+    map: Dijkstra
+}
+
+impl Chase {
+    /// Work out how monsters that can see up to sight can chase target.
+    pub fn new(world: &WorldMap, target: Location, sight: i32) -> Self {
+        Chase {
+            target: target,
+            visible: field_of_view(world, target, sight),
+            // The walk around can be longer than the line of sight.
+            map: Dijkstra::new(world, vec![target], sight as u32 * 4)
+        }
+    }
+}
+
+/// Let a monster take its turn. If it can see the target, it attacks it
+/// when next to it and otherwise walks downhill on the chase map towards
+/// it; if not, it wanders around at random. Closed doors in the way get
+/// opened. Returns the attack, if the monster made one.
+pub fn take_monster_turn<R: Rng>(rng: &mut R, world: &mut WorldMap,
+    ecs: &mut Ecs, chase: &Chase, monster: Entity, target: Entity)
+    -> Option<AttackResult>
+{
+    let (loc, stats) = match (ecs.position(monster),
+        ecs.monsters.get(monster)) {
+        (Some(loc), Some(stats)) => (loc, *stats),
+        _ => return None
+    };
+    let target_loc = chase.target;
+    debug_assert_eq!(ecs.position(target), Some(target_loc));
+    if is_dead(ecs, target) {
+        return None;
+    }
+
+    let sees_target = loc.euclidean(&target_loc) <= stats.sight &&
+        chase.visible.contains(&loc);
+    if sees_target && loc.chebyshev(&target_loc) == 1 {
+        return Some(attack(rng, ecs, monster, target));
+    }
+
+    let next = {
+        let can_move_to = |l: &Location| {
+            world.get_tile(*l).terrain.is_passable() && !ecs.is_blocked(*l)
+        };
+        if sees_target {
+            let here = chase.map.get(&loc);
+            chase.map.sorted_neighbors(&loc).into_iter()
+                .filter(|l| here.is_some() && chase.map.get(l) < here)
+                .filter(|l| can_move_to(l))
+                .next()
+        } else {
+            let options: Vec<Location> = world.get_adjacent(loc, true)
+                .into_iter()
+                .filter(|l| can_move_to(l))
+                .collect();
+            if options.is_empty() {
+                None
+            } else {
+                Some(options[rng.gen_range::<usize>(0, options.len())])
+            }
+        }
+    };
+
+    if let Some(next) = next {
+        if world.get_tile(next).terrain == Terrain::DoorClosed {
+            world.get_tile_mut(next).terrain = Terrain::DoorOpen;
+        } else {
+            ecs.set_position(monster, next);
+        }
+    }
+
+    None
+}
+
+#[test]
+fn test_monsters_chase_target() {
+    use config::GenerationConfig;
+    use self::rand::{StdRng, SeedableRng};
+
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        seed: 8,
+        door_chance: 0,
+        ..Default::default()
+    };
+    let (mut world, start) = WorldMap::generate(&config);
+    let mut ecs = Ecs::new();
+    let player = ecs.create();
+    ecs.blockers.insert(player, BlocksMovement);
+    ecs.set_position(player, start);
+
+    // A goblin somewhere it can see the player from.
+    let visible = field_of_view(&world, start, GOBLIN.sight);
+    let chase = Dijkstra::new(&world, vec![start], 100);
+    let spot = world.tiles()
+        .filter(|t| t.terrain == Terrain::Floor && visible.contains(&t.loc))
+        .map(|t| t.loc)
+        .max_by_key(|l| chase.get(l).unwrap())
+        .unwrap();
+    assert!(chase.get(&spot).unwrap() > 2);
+    let goblin = spawn_monster(&mut ecs, &GOBLIN, spot);
+
+    // It closes in a step at a time, then attacks the player.
+    let mut rng = StdRng::from_seed(&[1][..]);
+    let shared = Chase::new(&world, start, GOBLIN.sight);
+    let mut distance = chase.get(&spot).unwrap();
+    while distance > 1 {
+        take_monster_turn(&mut rng, &mut world, &mut ecs, &shared, goblin,
+            player);
+        let now = chase.get(&ecs.position(goblin).unwrap()).unwrap();
+        assert_eq!(now, distance - 1);
+        distance = now;
+    }
+    let attack = take_monster_turn(&mut rng, &mut world, &mut ecs, &shared,
+        goblin, player).unwrap();
+    assert_eq!((attack.attacker, attack.defender), (goblin, player));
+    assert_eq!(chase.get(&ecs.position(goblin).unwrap()), Some(1));
+    assert_eq!(ecs.position(player), Some(start));
+
+    // Deep monsters stay deep.
+    let mut table = monster_table::<StdRng>(1);
+    for _ in 0..100 {
+        let kind = table.generate(&mut rng);
+        assert!(kind == RAT || kind == GOBLIN);
+    }
+    table = monster_table::<StdRng>(5);
+    assert!((0..200).any(|_| table.generate(&mut rng) == TROLL));
+}
@@ -0,0 +1,287 @@
+use backend::{Backend, Key};
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Version of the replay format. Replays from other versions are refused.
+pub const REPLAY_VERSION: u32 = 1;
+
+// First token of every replay file.
+const MAGIC: &'static str = "roguelike-replay";
+
+// Keys that don't type a character, with their names in replay files.
+const SPECIAL_KEYS: [(Key, &'static str); 12] = [(Key::Up, "up"),
+    (Key::Down, "down"), (Key::Left, "left"), (Key::Right, "right"),
+    (Key::Escape, "escape"), (Key::Enter, "enter"),
+    (Key::Backspace, "backspace"), (Key::Tab, "tab"),
+    (Key::PageUp, "pageup"), (Key::PageDown, "pagedown"),
+    (Key::Home, "home"), (Key::End, "end")];
+
+/// Something that happened during a recorded game.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Key(Key),
+    // The game was saved on quitting. When it's picked up again the game
+    // carries on with a random number generator seeded from the old one.
+    Save
+}
+
+/// A recorded game: the seed it started from and everything the player did
+/// after that. Replay files consist of a "roguelike-replay <version>" line,
+/// a "seed <n>" line and then one event per line: "char <code point>" or
+/// "key <name>" for keypresses and "save" for saves.
+pub struct Replay {
+    pub seed: usize,
+    pub events: Vec<Event>
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse { line: usize, message: String }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Io(ref e) => write!(f, "{}", e),
+            ReplayError::Parse { line, ref message } =>
+                write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl ::std::error::Error for ReplayError {
+    fn description(&self) -> &str {
+        match *self {
+            ReplayError::Io(_) => "I/O error while loading replay",
+            ReplayError::Parse { ref message, .. } => message
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+impl Replay {
+    pub fn parse(text: &str) -> Result<Self, ReplayError> {
+        let error = |line: usize, message: String| {
+            Err(ReplayError::Parse { line: line + 1, message: message })
+        };
+
+        let mut lines = text.lines().enumerate()
+            .map(|(i, l)| (i, l.trim()))
+            .filter(|&(_, l)| !l.is_empty());
+        match lines.next() {
+            Some((_, line)) if line == format!("{} {}", MAGIC,
+                REPLAY_VERSION) => {},
+            Some((i, line)) if line.starts_with(MAGIC) =>
+                return error(i, format!("unsupported version \"{}\"",
+                    &line[MAGIC.len()..].trim())),
+            _ => return error(0, "not a replay file".to_string())
+        }
+        let seed = match lines.next() {
+            Some((i, line)) => match parse_pair(line) {
+                ("seed", value) => match value.parse::<usize>() {
+                    Ok(seed) => seed,
+                    Err(_) => return error(i,
+                        format!("invalid seed \"{}\"", value))
+                },
+                _ => return error(i, "expected \"seed <n>\"".to_string())
+            },
+            None => return error(1, "missing seed".to_string())
+        };
+
+        let mut events = Vec::new();
+        for (i, line) in lines {
+            let event = match parse_pair(line) {
+                ("char", value) => match value.parse::<u32>().ok()
+                    .and_then(::std::char::from_u32) {
+                    Some(c) => Event::Key(Key::Char(c)),
+                    None => return error(i,
+                        format!("invalid character \"{}\"", value))
+                },
+                ("key", value) => match SPECIAL_KEYS.iter()
+                    .find(|&&(_, name)| name == value) {
+                    Some(&(key, _)) => Event::Key(key),
+                    None => return error(i,
+                        format!("unknown key \"{}\"", value))
+                },
+                ("save", "") => Event::Save,
+                _ => return error(i,
+                    format!("unknown event \"{}\"", line))
+            };
+            events.push(event);
+        }
+
+        Ok(Replay {seed: seed, events: events})
+    }
+    pub fn load(path: &Path) -> Result<Self, ReplayError> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Replay::parse(&text)
+    }
+}
+
+// Split a line into its first word and the rest.
+fn parse_pair(line: &str) -> (&str, &str) {
+    match line.find(' ') {
+        Some(index) => (&line[..index], line[index + 1..].trim()),
+        None => (line, "")
+    }
+}
+
+// Describe an event as a line of a replay file.
+fn describe(event: Event) -> String {
+    match event {
+        Event::Key(Key::Char(c)) => format!("char {}", c as u32),
+        Event::Key(key) => {
+            let &(_, name) = SPECIAL_KEYS.iter()
+                .find(|&&(k, _)| k == key)
+                .unwrap();
+            format!("key {}", name)
+        },
+        Event::Save => "save".to_string()
+    }
+}
+
+/// Writes events to a replay file as they happen, so that a recording is
+/// complete up to whatever crashed the game.
+pub struct Recorder {
+    file: File
+}
+
+impl Recorder {
+    /// Start a new replay file for a game started from a seed.
+    pub fn create(path: &Path, seed: usize) -> io::Result<Self> {
+        let mut file = try!(File::create(path));
+        try!(write!(file, "{} {}\nseed {}\n", MAGIC, REPLAY_VERSION, seed));
+        Ok(Recorder {file: file})
+    }
+    /// Carry on recording to an existing replay file, e.g. after picking a
+    /// saved game back up.
+    pub fn append(path: &Path) -> io::Result<Self> {
+        let file = try!(OpenOptions::new().append(true).open(path));
+        Ok(Recorder {file: file})
+    }
+    pub fn record(&mut self, event: Event) -> io::Result<()> {
+        try!(writeln!(self.file, "{}", describe(event)));
+        self.file.flush()
+    }
+}
+
+/// Where the game's keypresses come from: a replay while it lasts, then the
+/// backend. Keys read from the backend are recorded if there's a recorder.
+/// While a replay plays, pressing + or - speeds it up or slows it down.
+pub struct Input {
+    replay: VecDeque<Event>,
+    // How long to wait before each key of the replay.
+    delay: Duration,
+    recorder: Option<Recorder>
+}
+
+impl Input {
+    pub fn new(recorder: Option<Recorder>) -> Self {
+        Input {
+            replay: VecDeque::new(),
+            delay: Duration::from_millis(0),
+            recorder: recorder
+        }
+    }
+    pub fn from_replay(replay: Replay, delay: Duration) -> Self {
+        Input {
+            replay: replay.events.into_iter().collect(),
+            delay: delay,
+            recorder: None
+        }
+    }
+    /// Wait for the next keypress. Returns None once the backend runs out
+    /// of keys.
+    pub fn next_key<B: Backend>(&mut self, backend: &mut B) -> Option<Key> {
+        if let Some(&Event::Key(key)) = self.replay.front() {
+            while let Some(pressed) = backend.poll_key() {
+                match pressed {
+                    Key::Char('+') => self.delay /= 2,
+                    Key::Char('-') => self.delay = cmp::max(self.delay * 2,
+                        Duration::from_millis(10)),
+                    _ => {}
+                }
+            }
+            self.replay.pop_front();
+            thread::sleep(self.delay);
+            return Some(key);
+        }
+
+        let key = backend.wait_key();
+        if let Some(key) = key {
+            self.record(Event::Key(key));
+        }
+
+        key
+    }
+    /// Whether the replay saved the game at this point. If so, that's taken
+    /// off the replay.
+    pub fn take_save(&mut self) -> bool {
+        let save = self.save_follows();
+        if save {
+            self.replay.pop_front();
+        }
+
+        save
+    }
+    /// Whether the replay saves the game next, without taking it off.
+    pub fn save_follows(&self) -> bool {
+        self.replay.front() == Some(&Event::Save)
+    }
+    /// Record that the game has been saved.
+    pub fn record_save(&mut self) {
+        self.record(Event::Save);
+    }
+    // Record an event if there's a recorder. A replay that can't be written
+    // to isn't worth losing the game over, so recording just stops.
+    fn record(&mut self, event: Event) {
+        let result = match self.recorder {
+            Some(ref mut recorder) => recorder.record(event),
+            None => return
+        };
+        if let Err(e) = result {
+            eprintln!("Stopped recording the replay: {}", e);
+            self.recorder = None;
+        }
+    }
+}
+
+#[test]
+fn test_replay() {
+    let path = ::std::env::temp_dir()
+        .join(format!("roguelike-test-{}.replay", ::std::process::id()));
+    let events = vec![Event::Key(Key::Char('o')), Event::Key(Key::Up),
+        Event::Key(Key::Char(' ')), Event::Key(Key::Escape), Event::Save,
+        Event::Key(Key::Char('g'))];
+    {
+        let mut recorder = Recorder::create(&path, 1234).unwrap();
+        for event in events[..4].iter() {
+            recorder.record(*event).unwrap();
+        }
+    }
+    // Recording carries on where it left off.
+    let mut recorder = Recorder::append(&path).unwrap();
+    for event in events[4..].iter() {
+        recorder.record(*event).unwrap();
+    }
+    let replay = Replay::load(&path).unwrap();
+    ::std::fs::remove_file(&path).unwrap();
+    assert_eq!(replay.seed, 1234);
+    assert_eq!(replay.events, events);
+
+    assert!(Replay::parse("roguelike-replay 0\nseed 1\n").is_err());
+    assert!(Replay::parse("roguelike-replay 1\nseed 1\nkey f13\n").is_err());
+}
@@ -0,0 +1,448 @@
+use dungeon::Dungeon;
+use message::MessageLog;
+use scheduler::Scheduler;
+use vault::Vault;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Version of the save format. Saves from other versions are refused, so
+/// this needs bumping whenever anything saved changes shape.
+pub const SAVE_VERSION: u32 = 2;
+
+// First token of every save file.
+const MAGIC: &'static str = "roguelike-save";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    // The file isn't a save, or it's been damaged.
+    Corrupt(String),
+    // The file was saved by a different version of the game.
+    Version(u32)
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref e) => write!(f, "{}", e),
+            SaveError::Corrupt(ref message) =>
+                write!(f, "save file is corrupt: {}", message),
+            SaveError::Version(version) =>
+                write!(f, "save file is from version {} but this game \
+                    reads version {}", version, SAVE_VERSION)
+        }
+    }
+}
+
+impl ::std::error::Error for SaveError {
+    fn description(&self) -> &str {
+        match *self {
+            SaveError::Io(_) => "I/O error while saving or loading",
+            SaveError::Corrupt(ref message) => message,
+            SaveError::Version(_) => "incompatible save file version"
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+/// Return an error for a save file that doesn't make sense.
+pub fn corrupt<T, S: Into<String>>(message: S) -> Result<T, SaveError> {
+    Err(SaveError::Corrupt(message.into()))
+}
+
+/// Builds up the text of a save file. Everything is written as tokens
+/// separated by spaces, except for strings, which are written as their
+/// length in bytes followed by a space and the string itself.
+pub struct SaveWriter {
+    out: String
+}
+
+impl SaveWriter {
+    pub fn new() -> Self {
+        SaveWriter {out: String::new()}
+    }
+    pub fn token<T: fmt::Display>(&mut self, token: T) {
+        self.out.push_str(&token.to_string());
+        self.out.push(' ');
+    }
+    pub fn string(&mut self, s: &str) {
+        self.token(s.len());
+        self.out.push_str(s);
+        self.out.push(' ');
+    }
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// Reads back what a SaveWriter wrote.
+pub struct SaveReader<'a> {
+    text: &'a str
+}
+
+impl<'a> SaveReader<'a> {
+    pub fn new(text: &'a str) -> Self {
+        SaveReader {text: text}
+    }
+    pub fn token<T: FromStr>(&mut self) -> Result<T, SaveError> {
+        let text = self.text.trim_start();
+        if text.is_empty() {
+            return corrupt("unexpected end of file");
+        }
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        self.text = &text[end..];
+        match text[..end].parse() {
+            Ok(t) => Ok(t),
+            Err(_) => corrupt(format!("unexpected \"{}\"", &text[..end]))
+        }
+    }
+    pub fn string(&mut self) -> Result<String, SaveError> {
+        let len = try!(self.token::<usize>());
+        // Skip the space between the length and the string.
+        let end = len + 1;
+        if !self.text.starts_with(' ') || self.text.len() < end ||
+            !self.text.is_char_boundary(end) {
+            return corrupt("string runs past the end of the file");
+        }
+        let s = self.text[1..end].to_string();
+        self.text = &self.text[end..];
+        Ok(s)
+    }
+    /// Fail unless everything has been read.
+    pub fn finish(self) -> Result<(), SaveError> {
+        if self.text.trim().is_empty() {
+            Ok(())
+        } else {
+            corrupt("unexpected data at the end of the file")
+        }
+    }
+}
+
+/// Things that can be written to a save file.
+pub trait Save {
+    fn save(&self, w: &mut SaveWriter);
+}
+
+/// Things that can be read back from a save file.
+pub trait Load: Sized {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError>;
+}
+
+macro_rules! save_as_token {
+    ($($t:ty),*) => {$(
+        impl Save for $t {
+            fn save(&self, w: &mut SaveWriter) {
+                w.token(self);
+            }
+        }
+        impl Load for $t {
+            fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+                r.token()
+            }
+        }
+    )*}
+}
+
+save_as_token!(bool, u8, i32, u32, u64, usize);
+
+impl Save for char {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(*self as u32);
+    }
+}
+
+impl Load for char {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        match ::std::char::from_u32(try!(r.token())) {
+            Some(c) => Ok(c),
+            None => corrupt("invalid character")
+        }
+    }
+}
+
+impl Save for String {
+    fn save(&self, w: &mut SaveWriter) {
+        w.string(self);
+    }
+}
+
+impl Load for String {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        r.string()
+    }
+}
+
+impl<A: Save, B: Save> Save for (A, B) {
+    fn save(&self, w: &mut SaveWriter) {
+        self.0.save(w);
+        self.1.save(w);
+    }
+}
+
+impl<A: Load, B: Load> Load for (A, B) {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let a = try!(A::load(r));
+        let b = try!(B::load(r));
+        Ok((a, b))
+    }
+}
+
+impl<A: Save, B: Save, C: Save> Save for (A, B, C) {
+    fn save(&self, w: &mut SaveWriter) {
+        self.0.save(w);
+        self.1.save(w);
+        self.2.save(w);
+    }
+}
+
+impl<A: Load, B: Load, C: Load> Load for (A, B, C) {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let a = try!(A::load(r));
+        let b = try!(B::load(r));
+        let c = try!(C::load(r));
+        Ok((a, b, c))
+    }
+}
+
+impl<T: Save> Save for Option<T> {
+    fn save(&self, w: &mut SaveWriter) {
+        match *self {
+            Some(ref t) => {
+                w.token(1);
+                t.save(w);
+            },
+            None => w.token(0)
+        }
+    }
+}
+
+impl<T: Load> Load for Option<T> {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        match try!(r.token::<u8>()) {
+            0 => Ok(None),
+            1 => Ok(Some(try!(T::load(r)))),
+            _ => corrupt("invalid option")
+        }
+    }
+}
+
+// Collections are written as their length followed by their elements.
+impl<T: Save> Save for Vec<T> {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.len());
+        for t in self.iter() {
+            t.save(w);
+        }
+    }
+}
+
+impl<T: Load> Load for Vec<T> {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let len = try!(r.token::<usize>());
+        // Don't trust the length with an allocation up front.
+        let mut v = Vec::new();
+        for _ in 0..len {
+            v.push(try!(T::load(r)));
+        }
+        Ok(v)
+    }
+}
+
+impl<T: Save + Eq + Hash> Save for HashSet<T> {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.len());
+        for t in self.iter() {
+            t.save(w);
+        }
+    }
+}
+
+impl<T: Load + Eq + Hash> Load for HashSet<T> {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let v: Vec<T> = try!(Load::load(r));
+        Ok(v.into_iter().collect())
+    }
+}
+
+impl<K: Save + Eq + Hash, V: Save> Save for HashMap<K, V> {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.len());
+        for (k, v) in self.iter() {
+            k.save(w);
+            v.save(w);
+        }
+    }
+}
+
+impl<K: Load + Eq + Hash, V: Load> Load for HashMap<K, V> {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let v: Vec<(K, V)> = try!(Load::load(r));
+        Ok(v.into_iter().collect())
+    }
+}
+
+/// Everything needed to carry on with a game.
+pub struct SavedGame {
+    pub dungeon: Dungeon,
+    pub scheduler: Scheduler,
+    pub log: MessageLog,
+    // The state of a random number generator can't be saved, so instead a
+    // seed is drawn from it when saving and the game carries on with a new
+    // generator seeded from that.
+    pub seed: usize
+}
+
+/// Write a game out as the text of a save file.
+pub fn write_game(game: &SavedGame) -> String {
+    let mut w = SaveWriter::new();
+    w.token(MAGIC);
+    w.token(SAVE_VERSION);
+    game.dungeon.save(&mut w);
+    game.scheduler.save(&mut w);
+    game.log.save(&mut w);
+    game.seed.save(&mut w);
+
+    w.finish()
+}
+
+/// Read a game back from the text of a save file. Vaults aren't saved, so
+/// the given ones are used for any levels generated from now on.
+pub fn read_game(text: &str, vaults: Vec<Vault>)
+    -> Result<SavedGame, SaveError>
+{
+    let mut r = SaveReader::new(text);
+    match r.token::<String>() {
+        Ok(ref magic) if *magic == MAGIC => {},
+        _ => return corrupt("not a save file")
+    }
+    let version = try!(r.token::<u32>());
+    if version != SAVE_VERSION {
+        return Err(SaveError::Version(version));
+    }
+    let mut dungeon = try!(Dungeon::load(&mut r));
+    dungeon.set_vaults(vaults);
+    let scheduler = try!(Scheduler::load(&mut r));
+    let log = try!(MessageLog::load(&mut r));
+    let seed = try!(usize::load(&mut r));
+    try!(r.finish());
+
+    Ok(SavedGame {
+        dungeon: dungeon,
+        scheduler: scheduler,
+        log: log,
+        seed: seed
+    })
+}
+
+/// Save a game to a file, replacing any save already there.
+pub fn save_game(path: &Path, game: &SavedGame) -> Result<(), SaveError> {
+    // Write to a separate file first so a failed save doesn't clobber the
+    // old one.
+    let temp = path.with_extension("tmp");
+    {
+        let mut file = try!(File::create(&temp));
+        try!(file.write_all(write_game(game).as_bytes()));
+        try!(file.sync_all());
+    }
+    try!(fs::rename(&temp, path));
+
+    Ok(())
+}
+
+/// Load a saved game and delete its file, so that each save can only be
+/// picked up once. Files that can't be loaded are left alone.
+pub fn load_game(path: &Path, vaults: Vec<Vault>)
+    -> Result<SavedGame, SaveError>
+{
+    let mut text = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut text));
+    let game = try!(read_game(&text, vaults));
+    try!(delete_save(path));
+
+    Ok(game)
+}
+
+/// Delete a save file, if there is one, e.g. when the player dies.
+pub fn delete_save(path: &Path) -> Result<(), SaveError> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => Ok(try!(result))
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    use config::GenerationConfig;
+
+    let config = GenerationConfig {
+        width: 80,
+        height: 50,
+        seed: 11,
+        ..Default::default()
+    };
+    let mut dungeon = Dungeon::new(config);
+    dungeon.descend();
+    let player = dungeon.player();
+    let player_loc = dungeon.player_loc();
+    dungeon.level_mut().explored.insert(player_loc);
+    let mut log = MessageLog::new();
+    log.add("You go down to depth 2.", 3);
+    log.add("Odd message\nwith 2 lines and a ü", 7);
+    let mut scheduler = Scheduler::new();
+    scheduler.next_actor(&mut dungeon.level_mut().entities);
+    let game = SavedGame {
+        dungeon: dungeon,
+        scheduler: scheduler,
+        log: log,
+        seed: 42
+    };
+
+    let path = ::std::env::temp_dir()
+        .join(format!("roguelike-test-{}.sav", ::std::process::id()));
+    save_game(&path, &game).unwrap();
+    let loaded = load_game(&path, Vec::new()).unwrap();
+    assert!(!path.exists());
+    assert_eq!(loaded.dungeon.depth(), 2);
+    assert_eq!(loaded.dungeon.player(), player);
+    assert_eq!(loaded.dungeon.player_loc(), game.dungeon.player_loc());
+    assert!(loaded.dungeon.level().world == game.dungeon.level().world);
+    assert_eq!(loaded.dungeon.level().explored,
+        game.dungeon.level().explored);
+    assert_eq!(loaded.dungeon.level().entities.entities(),
+        game.dungeon.level().entities.entities());
+    assert_eq!(loaded.dungeon.level().entities.entities_at(
+        loaded.dungeon.player_loc()), &[player]);
+    assert_eq!(loaded.scheduler.time(), game.scheduler.time());
+    assert_eq!(loaded.log.messages(), game.log.messages());
+
+    // Damaged and outdated saves are refused.
+    let text = write_game(&game);
+    match read_game(&text[..text.len() / 2], Vec::new()) {
+        Err(SaveError::Corrupt(_)) => {},
+        _ => panic!("expected a corrupt save")
+    }
+    match read_game("roguelike-save 0 1 2 3", Vec::new()) {
+        Err(SaveError::Version(0)) => {},
+        _ => panic!("expected an outdated save")
+    }
+    let old = text.replacen(&format!("{} {}", MAGIC, SAVE_VERSION),
+        &format!("{} 1", MAGIC), 1);
+    match read_game(&old, Vec::new()) {
+        Err(SaveError::Version(1)) => {},
+        _ => panic!("expected an outdated save")
+    }
+    match read_game("hello", Vec::new()) {
+        Err(SaveError::Corrupt(_)) => {},
+        _ => panic!("expected a corrupt save")
+    }
+}
@@ -0,0 +1,90 @@
+use ecs::{Ecs, Entity};
+use save::{Save, Load, SaveWriter, SaveReader, SaveError};
+
+/// Energy it takes to do one thing, like moving a step or attacking.
+pub const ACTION_COST: i32 = 100;
+
+/// Decides whose turn it is. Every actor gains energy equal to its speed
+/// each tick of game time and can act once it has ACTION_COST energy, so
+/// an actor with speed 200 acts twice for every time one with speed 100
+/// does.
+pub struct Scheduler {
+    time: u64
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {time: 0}
+    }
+    /// Return how many ticks of game time have passed.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+    /// Advance game time until an actor has enough energy to act, and
+    /// return it. The actor with the most energy goes first, with ties
+    /// going to the one with the lowest entity index (see Storage::iter).
+    /// The actor should spend some energy before this is called again, or
+    /// it will be returned again.
+    pub fn next_actor(&mut self, ecs: &mut Ecs) -> Entity {
+        assert!(ecs.actors.iter().any(|(_, a)| a.speed > 0));
+        loop {
+            let ready = ecs.actors.iter()
+                .filter(|&(_, a)| a.energy >= ACTION_COST)
+                .min_by_key(|&(_, a)| -a.energy)
+                .map(|(e, _)| e);
+            if let Some(actor) = ready {
+                return actor;
+            }
+
+            for (_, actor) in ecs.actors.iter_mut() {
+                actor.energy += actor.speed;
+            }
+            self.time += 1;
+        }
+    }
+    /// Use up some of an actor's energy after it acts.
+    pub fn spend(&self, ecs: &mut Ecs, actor: Entity, cost: i32) {
+        if let Some(a) = ecs.actors.get_mut(actor) {
+            a.energy -= cost;
+        }
+    }
+}
+
+// Actors' energy is saved with their entities, so only the time is left.
+impl Save for Scheduler {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.time);
+    }
+}
+
+impl Load for Scheduler {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        Ok(Scheduler {time: try!(r.token())})
+    }
+}
+
+#[test]
+fn test_scheduler() {
+    use ecs::Actor;
+    use std::collections::HashMap;
+
+    let mut ecs = Ecs::new();
+    let player = ecs.create();
+    let rat = ecs.create();
+    let troll = ecs.create();
+    ecs.actors.insert(player, Actor { speed: 100, energy: 0 });
+    ecs.actors.insert(rat, Actor { speed: 200, energy: 0 });
+    ecs.actors.insert(troll, Actor { speed: 50, energy: 0 });
+
+    let mut scheduler = Scheduler::new();
+    let mut turns = HashMap::new();
+    for _ in 0..70 {
+        let actor = scheduler.next_actor(&mut ecs);
+        *turns.entry(actor).or_insert(0) += 1;
+        scheduler.spend(&mut ecs, actor, ACTION_COST);
+    }
+    assert_eq!(turns[&player], 20);
+    assert_eq!(turns[&rat], 40);
+    assert_eq!(turns[&troll], 10);
+    assert_eq!(scheduler.time(), 20);
+}
@@ -0,0 +1,280 @@
+use backend::{Backend, Color, Key, WHITE, BLACK};
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Stdout, Write};
+use std::process::{Command, Stdio};
+
+/// Draws the game in a terminal with ANSI escape sequences and reads keys
+/// from it in raw mode, so it can be played over SSH without a window.
+/// Output can go to any writer, but keys always come from standard input.
+pub struct TerminalBackend<W: Write> {
+    out: W,
+    width: i32,
+    height: i32,
+    cells: Vec<(char, Color, Color)>,
+    // What the terminal shows, so that flushing only redraws what changed.
+    // None until a cell has been drawn.
+    shown: Vec<Option<(char, Color, Color)>>,
+    // Bytes read from the terminal that haven't been turned into keys yet.
+    pending: VecDeque<u8>,
+    // Terminal settings to put back when done, as saved by "stty -g".
+    saved_mode: Option<String>
+}
+
+// Run stty on the terminal standard input is connected to.
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = try!(Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output());
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl TerminalBackend<Stdout> {
+    /// Take over the terminal: switch to its alternate screen and put it
+    /// in raw mode until the backend is dropped.
+    pub fn open() -> io::Result<Self> {
+        let saved_mode = try!(stty(&["-g"]));
+        // Reads give up after a tenth of a second, so that a lone escape
+        // can be told apart from the start of an escape sequence.
+        try!(stty(&["raw", "-echo", "min", "0", "time", "1"]));
+        let size = try!(stty(&["size"]));
+        let mut numbers = size.split_whitespace().map(|n| n.parse::<i32>());
+        // Some terminals don't know their size and say it's 0 by 0.
+        let (height, width) = match (numbers.next(), numbers.next()) {
+            (Some(Ok(rows)), Some(Ok(columns))) if rows > 0 && columns > 0 =>
+                (rows, columns),
+            _ => (24, 80)
+        };
+
+        let mut backend = TerminalBackend::new(io::stdout(), width, height);
+        backend.saved_mode = Some(saved_mode);
+        // Alternate screen, hidden cursor, cleared screen.
+        try!(backend.out.write_all(b"\x1b[?1049h\x1b[?25l\x1b[2J"));
+        try!(backend.out.flush());
+
+        Ok(backend)
+    }
+}
+
+impl<W: Write> TerminalBackend<W> {
+    /// Create a backend drawing to a writer, leaving the terminal as it is.
+    pub fn new(out: W, width: i32, height: i32) -> Self {
+        let count = (width * height) as usize;
+        TerminalBackend {
+            out: out,
+            width: width,
+            height: height,
+            cells: vec![(' ', WHITE, BLACK); count],
+            shown: vec![None; count],
+            pending: VecDeque::new(),
+            saved_mode: None
+        }
+    }
+    // Read whatever has been typed, waiting up to a tenth of a second.
+    // Returns false if standard input can't be read any more.
+    fn read_input(&mut self) -> bool {
+        let mut buffer = [0; 64];
+        match io::stdin().read(&mut buffer) {
+            Ok(count) => {
+                self.pending.extend(buffer[..count].iter().cloned());
+                true
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => true,
+            Err(_) => false
+        }
+    }
+    // Turn the pending bytes into the next key, if there's a whole one.
+    // Returns Some(None) if the player asked to quit.
+    fn next_pending(&mut self) -> Option<Option<Key>> {
+        loop {
+            let (decoded, used) = {
+                let (front, back) = self.pending.as_slices();
+                let bytes: Vec<u8> = front.iter().chain(back.iter())
+                    .cloned()
+                    .collect();
+                match decode(&bytes) {
+                    Some(d) => d,
+                    None => return None
+                }
+            };
+            self.pending.drain(..used);
+            match decoded {
+                Decoded::Key(key) => return Some(Some(key)),
+                Decoded::Quit => return Some(None),
+                Decoded::Ignored => {}
+            }
+        }
+    }
+}
+
+impl<W: Write> Drop for TerminalBackend<W> {
+    fn drop(&mut self) {
+        if let Some(ref mode) = self.saved_mode {
+            // Reset colors, show the cursor and leave the alternate screen.
+            let _ = self.out.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+            let _ = self.out.flush();
+            let _ = stty(&[mode]);
+        }
+    }
+}
+
+impl<W: Write> Backend for TerminalBackend<W> {
+    fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+    fn put(&mut self, x: i32, y: i32, glyph: char, fg: Color, bg: Color) {
+        if x >= 0 && x < self.width && y >= 0 && y < self.height {
+            self.cells[(y * self.width + x) as usize] = (glyph, fg, bg);
+        }
+    }
+    fn flush(&mut self) {
+        let mut out = String::new();
+        let mut cursor = None;
+        let mut colors = None;
+        for (i, cell) in self.cells.iter().enumerate() {
+            if self.shown[i] == Some(*cell) {
+                continue;
+            }
+            let (x, y) = (i as i32 % self.width, i as i32 / self.width);
+            if cursor != Some((x, y)) {
+                write!(out, "\x1b[{};{}H", y + 1, x + 1).unwrap();
+            }
+            let (glyph, fg, bg) = *cell;
+            if colors != Some((fg, bg)) {
+                write!(out, "\x1b[38;2;{};{};{};48;2;{};{};{}m",
+                    fg.0, fg.1, fg.2, bg.0, bg.1, bg.2).unwrap();
+                colors = Some((fg, bg));
+            }
+            out.push(glyph);
+            cursor = Some((x + 1, y));
+            self.shown[i] = Some(*cell);
+        }
+        self.out.write_all(out.as_bytes())
+            .and_then(|_| self.out.flush())
+            .unwrap_or_else(|e| panic!("Failed to draw: {}", e));
+    }
+    fn wait_key(&mut self) -> Option<Key> {
+        loop {
+            if let Some(key) = self.next_pending() {
+                return key;
+            }
+            if !self.read_input() {
+                return None;
+            }
+        }
+    }
+    fn poll_key(&mut self) -> Option<Key> {
+        if self.pending.is_empty() {
+            self.read_input();
+        }
+        self.next_pending().and_then(|key| key)
+    }
+}
+
+// What some bytes of terminal input turned out to be.
+#[derive(Debug, PartialEq)]
+enum Decoded {
+    Key(Key),
+    // Ctrl-C or Ctrl-D. Raw mode stops them doing anything by themselves.
+    Quit,
+    // Something the game doesn't use, like a function key.
+    Ignored
+}
+
+// Decode the key at the start of some input, returning it along with how
+// many bytes it took up. Returns None if there isn't a whole key yet.
+// Escape sequences are assumed to arrive all at once.
+fn decode(bytes: &[u8]) -> Option<(Decoded, usize)> {
+    let first = match bytes.first() {
+        Some(b) => *b,
+        None => return None
+    };
+    let decoded = match first {
+        0x1b => return Some(decode_escape(bytes)),
+        b'\r' | b'\n' => Decoded::Key(Key::Enter),
+        b'\t' => Decoded::Key(Key::Tab),
+        0x7f | 0x08 => Decoded::Key(Key::Backspace),
+        0x03 | 0x04 => Decoded::Quit,
+        b if b < 0x20 => Decoded::Ignored,
+        _ => {
+            // A character, which may take up several bytes.
+            let len = match first {
+                b if b < 0x80 => 1,
+                b if b >= 0xf0 => 4,
+                b if b >= 0xe0 => 3,
+                _ => 2
+            };
+            if bytes.len() < len {
+                return None;
+            }
+            return Some(match ::std::str::from_utf8(&bytes[..len]) {
+                Ok(s) => (Decoded::Key(Key::Char(s.chars().next().unwrap())),
+                    len),
+                Err(_) => (Decoded::Ignored, 1)
+            });
+        }
+    };
+
+    Some((decoded, 1))
+}
+
+fn decode_escape(bytes: &[u8]) -> (Decoded, usize) {
+    match bytes.get(1) {
+        None => return (Decoded::Key(Key::Escape), 1),
+        Some(&b'[') | Some(&b'O') => {},
+        // Alt and another key.
+        Some(_) => return (Decoded::Ignored, 2)
+    }
+    // Parameters, then a final byte saying what the sequence is.
+    let end = match bytes[2..].iter().position(|b| *b >= 0x40 && *b <= 0x7e) {
+        Some(i) => i + 2,
+        None => return (Decoded::Ignored, bytes.len())
+    };
+    let key = match (&bytes[2..end], bytes[end]) {
+        (_, b'A') => Some(Key::Up),
+        (_, b'B') => Some(Key::Down),
+        (_, b'C') => Some(Key::Right),
+        (_, b'D') => Some(Key::Left),
+        (_, b'H') | (b"1", b'~') | (b"7", b'~') => Some(Key::Home),
+        (_, b'F') | (b"4", b'~') | (b"8", b'~') => Some(Key::End),
+        (b"5", b'~') => Some(Key::PageUp),
+        (b"6", b'~') => Some(Key::PageDown),
+        _ => None
+    };
+
+    (key.map_or(Decoded::Ignored, Decoded::Key), end + 1)
+}
+
+#[test]
+fn test_terminal_backend() {
+    assert_eq!(decode(b""), None);
+    assert_eq!(decode(b"o"), Some((Decoded::Key(Key::Char('o')), 1)));
+    assert_eq!(decode(b"\x1b"), Some((Decoded::Key(Key::Escape), 1)));
+    assert_eq!(decode(b"\x1b[Ag"), Some((Decoded::Key(Key::Up), 3)));
+    assert_eq!(decode(b"\x1bOD"), Some((Decoded::Key(Key::Left), 3)));
+    assert_eq!(decode(b"\x1b[5~"), Some((Decoded::Key(Key::PageUp), 4)));
+    assert_eq!(decode(b"\x1b[15~"), Some((Decoded::Ignored, 5)));
+    assert_eq!(decode(b"\r"), Some((Decoded::Key(Key::Enter), 1)));
+    assert_eq!(decode(b"\x03"), Some((Decoded::Quit, 1)));
+    assert_eq!(decode("é".as_bytes()), Some((Decoded::Key(Key::Char('é')), 2)));
+    assert_eq!(decode(&"é".as_bytes()[..1]), None);
+
+    // Only what changed gets drawn again.
+    let mut backend = TerminalBackend::new(Vec::new(), 4, 2);
+    backend.put(1, 1, '@', (255, 0, 0), BLACK);
+    backend.flush();
+    assert_eq!(String::from_utf8(backend.out.clone()).unwrap(),
+        "\x1b[1;1H\x1b[38;2;255;255;255;48;2;0;0;0m    \
+        \x1b[2;1H \x1b[38;2;255;0;0;48;2;0;0;0m@\
+        \x1b[38;2;255;255;255;48;2;0;0;0m  ");
+    backend.out.clear();
+    backend.put(3, 0, '#', WHITE, BLACK);
+    backend.flush();
+    assert_eq!(String::from_utf8(backend.out.clone()).unwrap(),
+        "\x1b[1;4H\x1b[38;2;255;255;255;48;2;0;0;0m#");
+}
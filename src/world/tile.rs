@@ -1,3 +1,5 @@
+use save::{Save, Load, SaveWriter, SaveReader, SaveError, corrupt};
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Location {
     pub x: i32,
@@ -14,6 +16,9 @@ impl Location {
 
         total_x + total_y
     }
+    pub fn chebyshev(&self, other: &Location) -> i32 {
+        ::std::cmp::max((self.x - other.x).abs(), (self.y - other.y).abs())
+    }
     pub fn euclidean(&self, other: &Location) -> i32 {
         let (x1, y1, x2, y2) = (self.x as f32, self.y as f32,
             other.x as f32, other.y as f32);
@@ -36,7 +41,50 @@ pub enum Terrain {
     Debug,
     Nothing,
     Floor,
-    Wall
+    Wall,
+    StairsUp,
+    StairsDown,
+    DoorClosed,
+    DoorOpen,
+    DoorLocked,
+    Rubble,
+    ShallowWater
+}
+
+impl Terrain {
+    /// Whether paths can go through the terrain. Closed doors count as
+    /// passable since they can be opened along the way.
+    pub fn is_passable(&self) -> bool {
+        match *self {
+            Terrain::Floor | Terrain::StairsUp | Terrain::StairsDown |
+                Terrain::DoorClosed | Terrain::DoorOpen | Terrain::Rubble |
+                Terrain::ShallowWater => true,
+            Terrain::Debug | Terrain::Nothing | Terrain::Wall |
+                Terrain::DoorLocked => false
+        }
+    }
+    /// Whether the terrain can't be seen through.
+    pub fn blocks_sight(&self) -> bool {
+        match *self {
+            Terrain::Wall | Terrain::DoorClosed | Terrain::DoorLocked => true,
+            Terrain::Debug | Terrain::Nothing | Terrain::Floor |
+                Terrain::StairsUp | Terrain::StairsDown |
+                Terrain::DoorOpen | Terrain::Rubble |
+                Terrain::ShallowWater => false
+        }
+    }
+    /// How many turns it takes to move onto the terrain, or None if it
+    /// can't be moved onto. Closed doors take an extra turn to open.
+    pub fn move_cost(&self) -> Option<u32> {
+        match *self {
+            Terrain::Floor | Terrain::StairsUp | Terrain::StairsDown |
+                Terrain::DoorOpen => Some(1),
+            Terrain::DoorClosed | Terrain::Rubble => Some(2),
+            Terrain::ShallowWater => Some(3),
+            Terrain::Debug | Terrain::Nothing | Terrain::Wall |
+                Terrain::DoorLocked => None
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
@@ -51,3 +99,40 @@ impl Tile {
     }
 }
 
+
+impl Save for Location {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.x);
+        w.token(self.y);
+    }
+}
+
+impl Load for Location {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let x = try!(r.token());
+        let y = try!(r.token());
+        Ok(Location::new(x, y))
+    }
+}
+
+// Terrain is saved as its position in this list, so new kinds of terrain
+// go on the end.
+const TERRAIN: [Terrain; 11] = [Terrain::Debug, Terrain::Nothing,
+    Terrain::Floor, Terrain::Wall, Terrain::StairsUp, Terrain::StairsDown,
+    Terrain::DoorClosed, Terrain::DoorOpen, Terrain::DoorLocked,
+    Terrain::Rubble, Terrain::ShallowWater];
+
+impl Save for Terrain {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(TERRAIN.iter().position(|t| t == self).unwrap());
+    }
+}
+
+impl Load for Terrain {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        match TERRAIN.get(try!(r.token::<usize>())) {
+            Some(t) => Ok(*t),
+            None => corrupt("unknown terrain")
+        }
+    }
+}
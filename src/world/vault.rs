@@ -0,0 +1,260 @@
+use feature::Feature;
+use tile::{Tile, Terrain, Location};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A hand-authored feature loaded from a text file. Vault files consist of
+/// a header of "key: value" lines, a line containing only "---", and then
+/// the map itself, for example:
+///
+/// ```text
+/// ; Lines starting with ';' are comments.
+/// name: Pillared hall
+/// weight: 2
+/// depth: 1-5
+/// legend: ~ floor
+/// legend: $ placeholder
+/// ---
+/// .......
+/// .#.$.#.
+/// .......
+/// ```
+///
+/// In the map, '.' is a floor, '#' is a wall and ' ' is not part of the
+/// vault. The legend assigns other glyphs a terrain ("floor" or "wall") or
+/// marks them as placeholders: floors whose positions are remembered so
+/// that something can be put there later (for now they're just drawn as
+/// floors). Vaults are surrounded by walls
+/// once they are drawn, just like rooms, so they don't need outer walls.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Vault {
+    pub name: String,
+    // Weight relative to the other features the vault can be picked from.
+    pub weight: u32,
+    // Range of dungeon depths (inclusive) the vault may appear at.
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub feature: Feature,
+    // Placeholder glyphs and their locations relative to the feature.
+    pub placeholders: Vec<(char, Location)>
+}
+
+#[derive(Debug)]
+pub enum VaultError {
+    Io(io::Error),
+    Parse { line: usize, message: String }
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VaultError::Io(ref e) => write!(f, "{}", e),
+            VaultError::Parse { line, ref message } =>
+                write!(f, "line {}: {}", line, message)
+        }
+    }
+}
+
+impl ::std::error::Error for VaultError {
+    fn description(&self) -> &str {
+        match *self {
+            VaultError::Io(_) => "I/O error while loading vault",
+            VaultError::Parse { ref message, .. } => message
+        }
+    }
+}
+
+impl From<io::Error> for VaultError {
+    fn from(e: io::Error) -> Self {
+        VaultError::Io(e)
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Glyph {
+    Terrain(Terrain),
+    Placeholder
+}
+
+impl Vault {
+    pub fn parse(text: &str) -> Result<Self, VaultError> {
+        let error = |line: usize, message: String| {
+            Err(VaultError::Parse { line: line + 1, message: message })
+        };
+
+        let mut name = None;
+        let mut weight = 1;
+        let (mut min_depth, mut max_depth) = (1, ::std::u32::MAX);
+        let mut legend = vec![
+            ('.', Glyph::Terrain(Terrain::Floor)),
+            ('#', Glyph::Terrain(Terrain::Wall))
+        ];
+
+        // Parse the header.
+        let mut lines = text.lines().enumerate();
+        let separator = loop {
+            let (i, line) = match lines.next() {
+                Some((i, line)) => (i, line.trim()),
+                None => return error(0, "missing \"---\" line".to_string())
+            };
+            if line == "---" {
+                break i;
+            }
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let (key, value) = match line.find(':') {
+                Some(index) =>
+                    (line[..index].trim(), line[index + 1..].trim()),
+                None => return error(i,
+                    format!("expected \"key: value\", found \"{}\"", line))
+            };
+            match key {
+                "name" => name = Some(value.to_string()),
+                "weight" => weight = match value.parse::<u32>() {
+                    Ok(w) if w > 0 => w,
+                    _ => return error(i,
+                        format!("invalid weight \"{}\"", value))
+                },
+                "depth" => {
+                    let mut bounds = value.splitn(2, '-')
+                        .map(|d| d.trim().parse::<u32>());
+                    let min = bounds.next();
+                    let max = bounds.next();
+                    match (min, max) {
+                        (Some(Ok(min)), None) => {
+                            min_depth = min;
+                            max_depth = min;
+                        },
+                        (Some(Ok(min)), Some(Ok(max))) if min <= max => {
+                            min_depth = min;
+                            max_depth = max;
+                        },
+                        _ => return error(i,
+                            format!("invalid depth \"{}\"", value))
+                    }
+                },
+                "legend" => {
+                    let mut parts = value.split_whitespace();
+                    let glyph = match parts.next() {
+                        Some(g) if g.chars().count() == 1 =>
+                            g.chars().next().unwrap(),
+                        _ => return error(i,
+                            format!("invalid legend glyph in \"{}\"", value))
+                    };
+                    let meaning = match parts.next() {
+                        Some("floor") => Glyph::Terrain(Terrain::Floor),
+                        Some("wall") => Glyph::Terrain(Terrain::Wall),
+                        Some("placeholder") => Glyph::Placeholder,
+                        _ => return error(i,
+                            format!("invalid legend entry \"{}\"", value))
+                    };
+                    legend.retain(|&(g, _)| g != glyph);
+                    legend.push((glyph, meaning));
+                },
+                _ => return error(i, format!("unknown key \"{}\"", key))
+            }
+        };
+
+        // Parse the map.
+        let mut tiles = Vec::new();
+        let mut placeholders = Vec::new();
+        let first_map_line = lines.clone().next().map_or(separator, |(i, _)| i);
+        for (y, (i, line)) in lines.enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                if c == ' ' {
+                    continue;
+                }
+                let loc = Location::new(x as i32, y as i32);
+                match legend.iter().find(|&&(g, _)| g == c) {
+                    Some(&(_, Glyph::Terrain(terrain))) =>
+                        tiles.push(Tile::new(loc, terrain)),
+                    Some(&(_, Glyph::Placeholder)) => {
+                        tiles.push(Tile::new(loc, Terrain::Floor));
+                        placeholders.push((c, loc));
+                    },
+                    None => return error(i,
+                        format!("glyph '{}' is not in the legend", c))
+                }
+            }
+        }
+        if !tiles.iter().any(|t| t.terrain == Terrain::Floor) {
+            return error(first_map_line, "vault has no floors".to_string());
+        }
+
+        // Move the top left corner of the vault to (0, 0).
+        let feature = Feature::new(tiles);
+        let (min_x, min_y) = (feature.min_x(), feature.min_y());
+        Ok(Vault {
+            name: name.unwrap_or_else(|| "unnamed".to_string()),
+            weight: weight,
+            min_depth: min_depth,
+            max_depth: max_depth,
+            feature: feature.translate(-min_x, -min_y),
+            placeholders: placeholders.into_iter()
+                .map(|(c, l)| (c, Location::new(l.x - min_x, l.y - min_y)))
+                .collect()
+        })
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, VaultError> {
+        let mut text = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut text));
+        Vault::parse(&text)
+    }
+    /// Load every ".vault" file in a directory, in order of file name so
+    /// that generation stays reproducible.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, VaultError> {
+        let mut paths = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |e| e == "vault") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        paths.iter().map(|p| Vault::load(p)).collect()
+    }
+    pub fn allowed_at(&self, depth: u32) -> bool {
+        depth >= self.min_depth && depth <= self.max_depth
+    }
+}
+
+#[test]
+fn test_parse_vault() {
+    let vault = Vault::parse("
+        ; A small shrine.
+        name: Shrine
+        weight: 3
+        depth: 2-4
+        legend: $ placeholder
+        ---
+        .#.
+        #$.
+         .").unwrap();
+
+    assert_eq!(vault.name, "Shrine");
+    assert_eq!(vault.weight, 3);
+    assert!(!vault.allowed_at(1) && vault.allowed_at(2) && vault.allowed_at(4));
+    assert_eq!(vault.placeholders, vec![('$', Location::new(1, 1))]);
+    assert_eq!(vault.feature.walls().count(), 2);
+    assert_eq!(vault.feature.floors().count(), 5);
+
+    // Placeholders are measured from the vault's top left corner, and
+    // several glyphs can be placeholders.
+    let vault = Vault::parse("legend: $ placeholder\nlegend: * placeholder\n\
+        ---\n\n   .$.\n   *..").unwrap();
+    assert_eq!(vault.placeholders,
+        vec![('$', Location::new(1, 0)), ('*', Location::new(0, 1))]);
+    assert_eq!(vault.feature.floors().count(), 6);
+
+    match Vault::parse("name: Empty\n\n---\n###") {
+        Err(VaultError::Parse { line, .. }) => assert_eq!(line, 4),
+        _ => panic!("vault without floors was accepted")
+    }
+    assert!(Vault::parse("name: Bad\n---\n.x.").is_err());
+    assert!(Vault::parse(".#.").is_err());
+}
@@ -1,74 +1,75 @@
-extern crate itertools;
-extern crate nalgebra;
 extern crate rand;
 
+use bsp::{BspTree, Rect};
+use config::{GenerationConfig, GenerationAlgorithm};
 use random::{RandomTable, IterRandomExt};
 use tile::{Tile, Terrain, Location};
+use dijkstra::Dijkstra;
 use feature::{Feature, VerticalAlignment, HorizontalAlignment};
+use save::{Save, Load, SaveWriter, SaveReader, SaveError, corrupt};
 use std::hash::Hash;
-use std::collections::HashMap;
 use std::collections::HashSet;
-use self::itertools::Itertools;
-use self::nalgebra::DMat;
 use self::rand::{Rng};
 
-/// A pathfinding map structure. A Dijkstra map lets you run pathfinding from
-/// any graph node it covers towards or away from the target nodes of the map.
-/// Currently the structure only supports underlying graphs with a fixed grid graph
-/// where the neighbors of each node must be the adjacent grid cells of that
-/// node.
-pub struct Dijkstra<'a> {
-    weights: HashMap<Location, u32>,
-    world: &'a WorldMap
+/// The connected regions of a map, as found by WorldMap::regions().
+pub struct Regions {
+    width: i32,
+    // Region of each cell of the map, indexed like the map's tiles.
+    labels: Vec<Option<usize>>,
+    regions: Vec<Vec<Location>>,
+    // Pairs of regions with a locked door between them.
+    locked_doors: Vec<(usize, usize)>
 }
 
-impl<'a> Dijkstra<'a> {
-    /// Create a new Dijkstra map up to limit distance from goals.
-    pub fn new(world: &'a WorldMap, goals: Vec<Location>, limit: u32) -> Self {
-        assert!(goals.len() > 0);
-
-        let mut weights = HashMap::new();
-        let mut edge = HashSet::new();
-
-        for n in goals.into_iter() {
-            edge.insert(n);
-        }
-
-        for dist in 0..(limit) {
-            for n in edge.iter() {
-                weights.insert(n.clone(), dist);
+impl Regions {
+    pub fn regions(&self) -> &[Vec<Location>] {
+        &self.regions
+    }
+    /// Return the index of the region containing a cell, if any.
+    pub fn label(&self, loc: Location) -> Option<usize> {
+        self.labels[(loc.y * self.width + loc.x) as usize]
+    }
+    /// Return the regions that can't be reached from start, even by going
+    /// through locked doors.
+    pub fn orphans(&self, start: Location) -> Vec<&Vec<Location>> {
+        let reachable = self.reachable(start);
+        self.regions.iter()
+            .enumerate()
+            .filter(|&(i, _)| !reachable[i])
+            .map(|(_, r)| r)
+            .collect()
+    }
+    /// Return the regions that can only be reached from start by going
+    /// through locked doors.
+    pub fn locked_away(&self, start: Location) -> Vec<&Vec<Location>> {
+        let start_label = self.label(start);
+        let reachable = self.reachable(start);
+        self.regions.iter()
+            .enumerate()
+            .filter(|&(i, _)| reachable[i] && Some(i) != start_label)
+            .map(|(_, r)| r)
+            .collect()
+    }
+    // Return whether each region can be reached from start's region by
+    // going through locked doors.
+    fn reachable(&self, start: Location) -> Vec<bool> {
+        let mut reachable = vec![false; self.regions.len()];
+        let mut stack: Vec<usize> = self.label(start).into_iter().collect();
+        while let Some(label) = stack.pop() {
+            if reachable[label] {
+                continue;
             }
-
-            let mut new_edge = HashSet::new();
-            for n in edge.iter() {
-                for m in world.get_adjacent(*n, true).into_iter()
-                    .filter(|l| world.get_tile(*l).terrain == Terrain::Floor) {
-                    if !weights.contains_key(&m) {
-                        new_edge.insert(m);
-                    }
+            reachable[label] = true;
+            for &(a, b) in self.locked_doors.iter() {
+                if a == label {
+                    stack.push(b);
+                } else if b == label {
+                    stack.push(a);
                 }
             }
-
-            edge = new_edge;
         }
 
-        Dijkstra {
-            weights: weights,
-            world: world
-        }
-    }
-
-    /// Return the neighbors of a cell (if any), sorted from downhill to
-    /// uphill.
-    pub fn sorted_neighbors(& self, node: &Location) -> Vec<Location> {
-        let mut ret = Vec::new();
-        for n in self.world.get_adjacent(*node, true).iter() {
-            if let Some(w) = self.weights.get(n) {
-                ret.push((w, n.clone()));
-            }
-        }
-        ret.sort_by(|&(w1, _), &(w2, _)| w1.cmp(w2));
-        ret.into_iter().map(|(_, n)| n).collect()
+        reachable
     }
 }
 
@@ -80,72 +81,80 @@ pub struct WorldMap {
 }
 
 impl WorldMap {
-    pub fn generate<R: Rng>(rng: &mut R, width: i32, height: i32) ->
+    /// Generate a map and the location to start on. Everything random comes
+    /// from the config's own seed.
+    pub fn generate(config: &GenerationConfig) -> (Self, Location) {
+        let mut rng = config.rng();
+        let rng = &mut rng;
+        assert!(config.width > 0);
+        assert!(config.height > 0);
+        assert!(config.edge_margin >= 1);
+
+        let (mut world, starting_loc) = match config.algorithm {
+            GenerationAlgorithm::Rooms => WorldMap::generate_rooms(rng, config),
+            GenerationAlgorithm::Bsp => WorldMap::generate_bsp(rng, config),
+            GenerationAlgorithm::Caves => WorldMap::generate_caves(rng, config)
+        };
+        if config.repair_connectivity {
+            world.connect_regions(rng, starting_loc);
+        }
+        world.add_loops(rng, config.loops, config.loop_min_ratio,
+            config.loop_max_wall);
+
+        (world, starting_loc)
+    }
+    fn generate_rooms<R: Rng>(rng: &mut R, config: &GenerationConfig) ->
         (Self, Location)
     {
-        assert!(width > 0);
-        assert!(height > 0);
-
-        let mut world = {
-            let mut tiles = Vec::new();
-            for j in 0..height {
-                for i in 0..width {
-                    tiles.push(Tile::new(Location::new(i, j),
-                        Terrain::Nothing));
-                }
-            }
-
-            WorldMap { width: width, height: height, tiles: tiles }
-        };
+        let (width, height) = (config.width, config.height);
+        let mut world = WorldMap::empty(width, height);
 
         // Populate the random feature generator table.
-        let feature_table = RandomTable::new(
-            vec![
-                (Box::new(|rng: &mut R| {
-                    let i = rng.gen_range::<i32>(2, 12);
-                    let j = rng.gen_range::<i32>(2, 12);
-                    Feature::room(i, j)
-                }), 1),
-                (Box::new(|rng: &mut R| {
-                    let r = rng.gen_range::<i32>(2, 12);
-                    Feature::diamond_room(r)
-                }), 1),
-                (Box::new(|rng: &mut R| {
-                    let r = rng.gen_range::<i32>(2, 12);
-                    Feature::circle_room(r)
-                }), 1)
-            ]);
+        let feature_table = WorldMap::feature_table(config);
 
         // Place first feature somewhere in the middle.
         // NOTE: Magic do/while syntax.
         let mut first_feature = feature_table.generate(rng);
         while {
+            let window = config.first_feature_window;
             let feature_x = rng.gen_range::<i32>(
-                width / 2 - 7, width / 2 + 7);
+                width / 2 - window, width / 2 + window);
             let feature_y = rng.gen_range::<i32>(
-                height / 2 - 7, height / 2 + 7);
+                height / 2 - window, height / 2 + window);
             let feat_loc = Location::new(feature_x, feature_y);
             first_feature = first_feature.place(VerticalAlignment::Center,
                 HorizontalAlignment::Center, feat_loc);
 
-            !world.can_fit(&first_feature)
+            !world.can_fit(&first_feature, config.edge_margin)
         } {}
 
         // Draw first feature.
         world.draw_feature(&first_feature);
         world.surround_floors_with_walls();
 
+        // Walls that are part of features (e.g. the pillars of a vault),
+        // which should be left alone below.
+        let mut feature_walls: HashSet<Location> =
+            first_feature.walls().cloned().collect();
+
         // Try drawing more features each connected by hallways.
-        for _ in 0..300 {
-            // Pick a random wall in the world.
+        for _ in 0..config.attempts {
+            // Pick a random wall in the world, leaving the walls on either
+            // side of doors alone.
+            let is_door = |l: &Location| {
+                let terrain = world.get_tile(*l).terrain;
+                terrain == Terrain::DoorClosed || terrain == Terrain::DoorLocked
+            };
             let rand_wall = world.tiles()
-                .filter(|t| t.terrain == Terrain::Wall)
+                .filter(|t| t.terrain == Terrain::Wall &&
+                    !world.get_adjacent(t.loc, false).iter().any(&is_door))
                 .random(rng)
                 .clone();
 
             // Draw a hallway attached to it.
             world.get_tile_mut(rand_wall.loc).terrain = Terrain::Nothing;
-            let hallway_len = rng.gen_range::<i32>(5, 15);
+            let hallway_len = rng.gen_range::<i32>(
+                config.hallway_length.0, config.hallway_length.1);
             let mut orientations = vec![
                 (VerticalAlignment::Center, HorizontalAlignment::Left),
                 (VerticalAlignment::Center, HorizontalAlignment::Right),
@@ -158,7 +167,7 @@ impl WorldMap {
                 let is_horiz = vert == VerticalAlignment::Center;
                 let hallway = Feature::hallway(hallway_len, is_horiz)
                     .place(vert, horiz, rand_wall.loc);
-                if world.can_fit(&hallway) {
+                if world.can_fit(&hallway, config.edge_margin) {
                     orientation = Some((vert, horiz));
                     break;
                 }
@@ -195,15 +204,49 @@ impl WorldMap {
                     // Generate a random feature attached to the hallway.
                     world.get_tile_mut(feat_orientation.2).terrain =
                         Terrain::Nothing;
-                    let feature = feature_table.generate(rng)
-                        .place(feat_orientation.0, feat_orientation.1,
-                            feat_orientation.2);
-                    if world.can_fit(&feature) {
+                    // Try it in each orientation (in random order) until
+                    // one fits. The feature must have a floor where it
+                    // meets the hallway; vaults don't always.
+                    let unoriented = feature_table.generate(rng);
+                    let mirror = rng.gen::<bool>();
+                    let mut turns = vec![0, 1, 2, 3];
+                    rng.shuffle(&mut turns);
+                    let placed = turns.iter()
+                        .map(|t| {
+                            let rotated = unoriented.rotate(*t);
+                            if mirror { rotated.mirror_horizontal() }
+                            else { rotated }
+                        })
+                        .map(|f| f.place(feat_orientation.0,
+                            feat_orientation.1, feat_orientation.2))
+                        .find(|f| f.floors().any(|l| *l == feat_orientation.2)
+                            && world.can_fit(f, config.edge_margin));
+                    if let Some(feature) = placed {
                         // Draw the feature.
                         world.draw_feature(&feature);
+                        feature_walls.extend(feature.walls().cloned());
                         world.get_tile_mut(feat_orientation.2).terrain =
                             Terrain::Floor;
                         world.surround_floors_with_walls();
+
+                        // Maybe put a door where the hallway meets the wall,
+                        // as long as there's a floor straight through it.
+                        // Hallways can leave from walls that only touch a
+                        // floor diagonally, like the corners of circles.
+                        let to = feat_orientation.2;
+                        let behind = Location::new(
+                            rand_wall.loc.x - (to.x - rand_wall.loc.x).signum(),
+                            rand_wall.loc.y - (to.y - rand_wall.loc.y).signum());
+                        if world.get_tile(behind).terrain == Terrain::Floor &&
+                            rng.gen_range::<u32>(0, 100) < config.door_chance {
+                            world.get_tile_mut(rand_wall.loc).terrain =
+                                if rng.gen_range::<u32>(0, 100) <
+                                    config.locked_door_chance {
+                                    Terrain::DoorLocked
+                                } else {
+                                    Terrain::DoorClosed
+                                };
+                        }
                     } else {
                         world.undraw_feature(&hallway);
                         world.get_tile_mut(rand_wall.loc).terrain =
@@ -221,6 +264,7 @@ impl WorldMap {
         // to them in cardinal directions.
         let make_floor_locs: Vec<Location> = world.tiles()
             .filter(|t| t.terrain == Terrain::Wall &&
+                !feature_walls.contains(&t.loc) &&
                 world.get_adjacent(t.loc, false).iter()
                     .filter(|a| world.get_tile(**a).terrain == Terrain::Floor)
                     .count() >= 3)
@@ -236,50 +280,354 @@ impl WorldMap {
 
         (world, starting_loc)
     }
+    fn generate_bsp<R: Rng>(rng: &mut R, config: &GenerationConfig) ->
+        (Self, Location)
+    {
+        assert!(config.bsp_min_leaf >= 3);
+        let margin = config.edge_margin;
+        let mut world = WorldMap::empty(config.width, config.height);
+
+        // Split the area inside the margins and fill in each leaf.
+        let bounds = Rect::new(margin, margin,
+            config.width - 2 * margin, config.height - 2 * margin);
+        let tree = BspTree::split(rng, bounds, config.bsp_min_leaf);
+        let rooms = world.draw_bsp_tree(rng, &tree, config);
+        world.surround_floors_with_walls();
+
+        // Pick a random floor in a random room to start on.
+        let starting_loc = *rooms.iter().random(rng).floors().random(rng);
+
+        (world, starting_loc)
+    }
+    // Draw a room in each leaf under the given node and connect the rooms
+    // of sibling nodes with corridors. Returns the drawn rooms.
+    fn draw_bsp_tree<R: Rng>(&mut self, rng: &mut R, tree: &BspTree,
+        config: &GenerationConfig) -> Vec<Feature>
+    {
+        match *tree {
+            BspTree::Leaf(rect) => {
+                // Leave a border of 1 inside the leaf for the walls.
+                let pick_size = |rng: &mut R, space: i32| {
+                    let (min_size, max_size) = config.room_size;
+                    let min_size = ::std::cmp::min(min_size, space);
+                    let max_size = ::std::cmp::min(max_size, space + 1);
+                    if max_size > min_size {
+                        rng.gen_range::<i32>(min_size, max_size)
+                    } else { min_size }
+                };
+                let room_width = pick_size(rng, rect.width - 2);
+                let room_height = pick_size(rng, rect.height - 2);
+                let x = rect.x + 1 +
+                    rng.gen_range::<i32>(0, rect.width - 2 - room_width + 1);
+                let y = rect.y + 1 +
+                    rng.gen_range::<i32>(0, rect.height - 2 - room_height + 1);
+                let room = Feature::room(room_width, room_height)
+                    .translate(x, y);
+                self.draw_feature(&room);
+
+                vec![room]
+            },
+            BspTree::Split(ref a, ref b) => {
+                let mut rooms = self.draw_bsp_tree(rng, a, config);
+                let other_rooms = self.draw_bsp_tree(rng, b, config);
+                let from = *rooms.iter().random(rng).floors().random(rng);
+                let to = *other_rooms.iter().random(rng).floors().random(rng);
+                self.draw_corridor(rng, from, to);
+                rooms.extend(other_rooms);
+
+                rooms
+            }
+        }
+    }
+    // Draw an L-shaped corridor of floors between two locations.
+    fn draw_corridor<R: Rng>(&mut self, rng: &mut R, from: Location,
+        to: Location)
+    {
+        let corner = if rng.gen::<bool>() {
+            Location::new(to.x, from.y)
+        } else {
+            Location::new(from.x, to.y)
+        };
+        for &(start, end) in [(from, corner), (corner, to)].iter() {
+            let hallway = if start.y == end.y {
+                Feature::hallway((end.x - start.x).abs() + 1, true)
+                    .place(VerticalAlignment::Center,
+                        if start.x <= end.x { HorizontalAlignment::Left }
+                        else { HorizontalAlignment::Right }, start)
+            } else {
+                Feature::hallway((end.y - start.y).abs() + 1, false)
+                    .place(if start.y <= end.y { VerticalAlignment::Top }
+                        else { VerticalAlignment::Bottom },
+                        HorizontalAlignment::Center, start)
+            };
+            self.draw_feature(&hallway);
+        }
+    }
+    fn generate_caves<R: Rng>(rng: &mut R, config: &GenerationConfig) ->
+        (Self, Location)
+    {
+        assert!(config.cave_fill_percent <= 100);
+        let margin = config.edge_margin;
+        assert!(config.width > 2 * margin && config.height > 2 * margin,
+            "no room for caves inside the edge margin");
+        let mut world = WorldMap::empty(config.width, config.height);
+        let in_margin = |loc: Location| {
+            loc.x < margin || loc.y < margin ||
+                loc.x >= config.width - margin ||
+                loc.y >= config.height - margin
+        };
+
+        // Seed the map with noise. Cells in the margin are always walls.
+        for tile in world.tiles.iter_mut() {
+            tile.terrain = if in_margin(tile.loc) ||
+                rng.gen_range::<u32>(0, 100) < config.cave_fill_percent {
+                Terrain::Wall
+            } else {
+                Terrain::Floor
+            };
+        }
+
+        // Smooth the noise out into caverns.
+        for _ in 0..config.cave_smoothing_passes {
+            let next: Vec<Terrain> = world.tiles()
+                .map(|t| {
+                    // Treat cells off the edge of the map as walls.
+                    let walls = 8 - world.get_adjacent(t.loc, true).iter()
+                        .filter(|l| world.get_tile(**l).terrain ==
+                            Terrain::Floor)
+                        .count() as u32;
+                    let limit = if t.terrain == Terrain::Wall {
+                        config.cave_survival_limit
+                    } else {
+                        config.cave_birth_limit
+                    };
+                    if in_margin(t.loc) || walls >= limit {
+                        Terrain::Wall
+                    } else {
+                        Terrain::Floor
+                    }
+                })
+                .collect();
+            for (tile, terrain) in world.tiles.iter_mut().zip(next) {
+                tile.terrain = terrain;
+            }
+        }
+
+        // Find the separate caverns, largest first.
+        let mut regions = world.regions().regions().to_vec();
+        if regions.is_empty() {
+            // Very full or small maps can smooth away every floor, so
+            // leave at least somewhere to stand.
+            let middle = Location::new(config.width / 2, config.height / 2);
+            world.get_tile_mut(middle).terrain = Terrain::Floor;
+            regions.push(vec![middle]);
+        }
+        regions.sort_by(|a, b| b.len().cmp(&a.len()));
+        let largest = regions.remove(0);
+        for region in regions.iter() {
+            if config.cave_join_regions {
+                let from = *region.iter().random(rng);
+                let to = *largest.iter().random(rng);
+                world.draw_corridor(rng, from, to);
+            } else {
+                for loc in region.iter() {
+                    world.get_tile_mut(*loc).terrain = Terrain::Nothing;
+                }
+            }
+        }
+
+        // Keep only the walls bordering floors.
+        for tile in world.tiles.iter_mut()
+            .filter(|t| t.terrain == Terrain::Wall) {
+            tile.terrain = Terrain::Nothing;
+        }
+        world.surround_floors_with_walls();
+
+        // Pick a random floor in the largest cavern to start on.
+        let starting_loc = *largest.iter().random(rng);
+
+        (world, starting_loc)
+    }
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+    pub fn height(&self) -> i32 {
+        self.height
+    }
     pub fn tiles(&self) -> ::std::slice::Iter<Tile> {
         self.tiles.iter()
     }
     pub fn get_tile(&self, loc: Location) -> &Tile {
-        let index = (loc.y * self.width + loc.x) as usize;
+        let index = self.index(loc);
         assert!(index < self.tiles.len());
         &self.tiles[index]
     }
     pub fn get_tile_mut(&mut self, loc: Location) -> &mut Tile {
-        let index = (loc.y * self.width + loc.x) as usize;
+        let index = self.index(loc);
         assert!(index < self.tiles.len());
         &mut self.tiles[index]
     }
-    pub fn create_dijkstra_map(&self, goals: &Vec<Location>) -> DMat<i32> {
-        // Create a matrix for each location in the map, with each
-        // score set very high.
-        let mut dmap = DMat::from_elem(self.height as usize,
-            self.width as usize, ::std::i32::MAX - 2);
-
-        // Set score of goals to 0.
-        goals.iter().foreach(|g| dmap[g.to_matrix_index()] = 0);
-
-        // Update matrix until no changes have been made.
-        let mut changed = true;
-        while changed {
-            changed = false;
-            self.tiles()
-                .filter(|t| t.terrain == Terrain::Floor)
-                .foreach(|t| {
-                    let smallest = self.get_adjacent(t.loc, true).iter()
-                        .map(|l| dmap[l.to_matrix_index()])
-                        .min()
-                        .unwrap();
-
-                    if dmap[t.loc.to_matrix_index()] >= smallest + 2 {
-                        dmap[t.loc.to_matrix_index()] = smallest + 1;
-                        changed = true;
+    /// Label the regions of the map: groups of passable cells that can
+    /// reach each other by (possibly diagonal) steps. Locked doors aren't
+    /// part of any region, but the regions they lead between are noted.
+    pub fn regions(&self) -> Regions {
+        let connects = |t: &Tile| t.terrain.is_passable();
+        let mut labels = vec![None; self.tiles.len()];
+        let mut regions = Vec::new();
+        for tile in self.tiles().filter(|t| connects(t)) {
+            if labels[self.index(tile.loc)].is_some() {
+                continue;
+            }
+
+            let label = regions.len();
+            let mut region = Vec::new();
+            let mut stack = vec![tile.loc];
+            labels[self.index(tile.loc)] = Some(label);
+            while let Some(loc) = stack.pop() {
+                region.push(loc);
+                for adj in self.get_adjacent(loc, true).into_iter()
+                    .filter(|l| connects(self.get_tile(*l))) {
+                    let index = self.index(adj);
+                    if labels[index].is_none() {
+                        labels[index] = Some(label);
+                        stack.push(adj);
                     }
-                });
+                }
+            }
+            regions.push(region);
         }
 
-        dmap
+        let mut locked_doors = Vec::new();
+        for door in self.tiles().filter(|t| t.terrain == Terrain::DoorLocked) {
+            let mut sides: Vec<usize> = self.get_adjacent(door.loc, true)
+                .iter()
+                .filter_map(|l| labels[self.index(*l)])
+                .collect();
+            sides.sort();
+            sides.dedup();
+            for (i, a) in sides.iter().enumerate() {
+                for b in sides[i + 1..].iter() {
+                    locked_doors.push((*a, *b));
+                }
+            }
+        }
+
+        Regions {
+            width: self.width,
+            labels: labels,
+            regions: regions,
+            locked_doors: locked_doors
+        }
+    }
+    /// Carve tunnels joining every region that can't be reached from start
+    /// to the one that can. Returns the number of tunnels carved.
+    pub fn connect_regions<R: Rng>(&mut self, rng: &mut R, start: Location)
+        -> usize
+    {
+        let regions = self.regions();
+        let orphans = regions.orphans(start);
+        if orphans.is_empty() {
+            return 0;
+        }
+
+        // Anything behind a locked door counts as connected already.
+        assert!(regions.label(start).is_some(), "start is not passable");
+        let reachable = regions.reachable(start);
+        let mut connected: Vec<Location> = regions.regions().iter()
+            .enumerate()
+            .filter(|&(i, _)| reachable[i])
+            .flat_map(|(_, r)| r.iter().cloned())
+            .collect();
+        for region in orphans.iter() {
+            // Dig from a random cell of the orphan to the closest
+            // connected cell.
+            let from = *region.iter().random(rng);
+            let to = *connected.iter()
+                .min_by_key(|l| l.manhattan(&from))
+                .unwrap();
+            self.draw_corridor(rng, from, to);
+            connected.extend(region.iter().cloned());
+        }
+        self.surround_floors_with_walls();
+
+        orphans.len()
+    }
+    /// Knock passages through walls to add up to count loops to the map.
+    /// A passage goes straight through at most max_wall walls between two
+    /// floors, and is only made if walking between those floors currently
+    /// takes more than min_ratio times as many steps as the passage would.
+    /// Returns the number of loops added.
+    pub fn add_loops<R: Rng>(&mut self, rng: &mut R, count: u32,
+        min_ratio: u32, max_wall: i32) -> u32
+    {
+        if count == 0 {
+            return 0;
+        }
+
+        // Find every straight run of walls with floors at both ends.
+        let mut candidates = Vec::new();
+        for tile in self.tiles().filter(|t| t.terrain == Terrain::Floor) {
+            for &(dx, dy) in [(1, 0), (0, 1)].iter() {
+                let mut walls = Vec::new();
+                for i in 1..max_wall + 2 {
+                    let loc = Location::new(tile.loc.x + i * dx,
+                        tile.loc.y + i * dy);
+                    if loc.x >= self.width || loc.y >= self.height {
+                        break;
+                    }
+                    match self.get_tile(loc).terrain {
+                        Terrain::Wall => walls.push(loc),
+                        Terrain::Floor if walls.len() > 0 => {
+                            candidates.push((tile.loc, loc, walls));
+                            break;
+                        },
+                        _ => break
+                    }
+                }
+            }
+        }
+        rng.shuffle(&mut candidates);
+
+        let mut added = 0;
+        for &(from, to, ref walls) in candidates.iter() {
+            if added == count {
+                break;
+            }
+
+            // An earlier passage may have made this one pointless. Dijkstra
+            // maps stop short of their limit, hence the extra step.
+            let limit = (walls.len() as u32 + 1) * min_ratio + 1;
+            if Dijkstra::new(self, vec![from], limit).get(&to).is_some() {
+                continue;
+            }
+
+            for loc in walls.iter() {
+                self.get_tile_mut(*loc).terrain = Terrain::Floor;
+            }
+            added += 1;
+        }
+        self.surround_floors_with_walls();
+
+        added
+    }
+    fn index(&self, loc: Location) -> usize {
+        (loc.y * self.width + loc.x) as usize
     }
-    fn get_adjacent(&self, loc: Location, with_diag: bool) -> Vec<Location> {
+    fn empty(width: i32, height: i32) -> Self {
+        let mut tiles = Vec::new();
+        for j in 0..height {
+            for i in 0..width {
+                tiles.push(Tile::new(Location::new(i, j), Terrain::Nothing));
+            }
+        }
+
+        WorldMap { width: width, height: height, tiles: tiles }
+    }
+    /// Return the cells next to a location that are within the map,
+    /// optionally including diagonal ones.
+    pub fn get_adjacent(&self, loc: Location, with_diag: bool)
+        -> Vec<Location>
+    {
         let mut adjacent = Vec::new();
         for i in -1..2 {
             for j in -1..2 {
@@ -300,13 +648,49 @@ impl WorldMap {
 
         return adjacent;
     }
-    fn can_fit(&self, feature: &Feature) -> bool {
+    fn feature_table<R: Rng>(config: &GenerationConfig) ->
+        RandomTable<Feature, R>
+    {
+        let (min_size, max_size) = config.room_size;
+        let (min_radius, max_radius) = config.room_radius;
+        let mut features: Vec<(Box<Fn(&mut R) -> Feature>, u32)> = Vec::new();
+        if config.room_weight > 0 {
+            features.push((Box::new(move |rng: &mut R| {
+                let i = rng.gen_range::<i32>(min_size, max_size);
+                let j = rng.gen_range::<i32>(min_size, max_size);
+                Feature::room(i, j)
+            }), config.room_weight));
+        }
+        if config.diamond_room_weight > 0 {
+            features.push((Box::new(move |rng: &mut R| {
+                let r = rng.gen_range::<i32>(min_radius, max_radius);
+                Feature::diamond_room(r)
+            }), config.diamond_room_weight));
+        }
+        if config.circle_room_weight > 0 {
+            features.push((Box::new(move |rng: &mut R| {
+                let r = rng.gen_range::<i32>(min_radius, max_radius);
+                Feature::circle_room(r)
+            }), config.circle_room_weight));
+        }
+        for vault in config.vaults.iter()
+            .filter(|v| v.allowed_at(config.depth)) {
+            let feature = vault.feature.clone();
+            features.push((Box::new(move |_: &mut R| feature.clone()),
+                vault.weight));
+        }
+        assert!(features.len() > 0);
+
+        RandomTable::new(features)
+    }
+    fn can_fit(&self, feature: &Feature, margin: i32) -> bool {
         // Check if it fits in the world.
-        // NOTE: Keep 1 space away from the edges as a hack to
+        // NOTE: Keep at least 1 space away from the edges as a hack to
         // make sure all floors are surrounded by walls.
         for tile in feature.iter() {
-            if tile.loc.x <= 0 || tile.loc.y <= 0 ||
-                tile.loc.x >= self.width - 1 || tile.loc.y >= self.height - 1 {
+            if tile.loc.x < margin || tile.loc.y < margin ||
+                tile.loc.x >= self.width - margin ||
+                tile.loc.y >= self.height - margin {
                 return false;
             }
 
@@ -344,7 +728,209 @@ impl WorldMap {
 }
 
 
-#[derive(Copy, Clone, Debug)]
-pub struct Entity {
-    id: u64
+impl Save for WorldMap {
+    fn save(&self, w: &mut SaveWriter) {
+        w.token(self.width);
+        w.token(self.height);
+        // Tile locations follow from their order.
+        for tile in self.tiles.iter() {
+            tile.terrain.save(w);
+        }
+    }
+}
+
+impl Load for WorldMap {
+    fn load(r: &mut SaveReader) -> Result<Self, SaveError> {
+        let width = try!(r.token::<i32>());
+        let height = try!(r.token::<i32>());
+        if width <= 0 || height <= 0 {
+            return corrupt("invalid map size");
+        }
+        let mut tiles = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                tiles.push(Tile::new(Location::new(x, y),
+                    try!(Terrain::load(r))));
+            }
+        }
+        Ok(WorldMap {width: width, height: height, tiles: tiles})
+    }
+}
+
+#[test]
+fn test_generate_is_reproducible() {
+    let config = GenerationConfig {
+        width: 80,
+        height: 50,
+        seed: 42,
+        ..Default::default()
+    };
+    let (world1, start1) = WorldMap::generate(&config);
+    let (world2, start2) = WorldMap::generate(&config);
+    assert!(world1 == world2);
+    assert_eq!(start1, start2);
+    assert_eq!(world1.get_tile(start1).terrain, Terrain::Floor);
+
+    let other = GenerationConfig { seed: 43, ..config };
+    let (world3, _) = WorldMap::generate(&other);
+    assert!(world1 != world3);
+}
+
+#[test]
+fn test_generated_maps_are_connected() {
+    for &algorithm in [GenerationAlgorithm::Rooms, GenerationAlgorithm::Bsp,
+        GenerationAlgorithm::Caves].iter() {
+        // Repairing shouldn't be needed, but check it leaves things
+        // connected on some seeds too.
+        for seed in 0..1200 {
+            let config = GenerationConfig {
+                width: 50,
+                height: 30,
+                algorithm: algorithm,
+                seed: seed,
+                attempts: 100,
+                bsp_min_leaf: 8,
+                repair_connectivity: seed >= 1000,
+                ..Default::default()
+            };
+            let (world, start) = WorldMap::generate(&config);
+            let orphans = world.regions().orphans(start).len();
+            assert!(orphans == 0, "{:?} seed {} has {} unreachable regions",
+                algorithm, seed, orphans);
+        }
+    }
+}
+
+#[test]
+fn test_doors_are_at_junctions() {
+    for seed in 0..20 {
+        let config = GenerationConfig {
+            width: 80,
+            height: 50,
+            seed: seed,
+            door_chance: 100,
+            locked_door_chance: 50,
+            loops: 0,
+            ..Default::default()
+        };
+        let (world, _) = WorldMap::generate(&config);
+        let open = |x: i32, y: i32| {
+            world.get_tile(Location::new(x, y)).terrain != Terrain::Wall
+        };
+        let doors: Vec<Location> = world.tiles()
+            .filter(|t| t.terrain == Terrain::DoorClosed ||
+                t.terrain == Terrain::DoorLocked)
+            .map(|t| t.loc)
+            .collect();
+        assert!(!doors.is_empty());
+        // Each door joins two spaces on opposite sides and sits in a wall.
+        for door in doors {
+            let (x, y) = (door.x, door.y);
+            let vertical = open(x, y - 1) && open(x, y + 1) &&
+                !open(x - 1, y) && !open(x + 1, y);
+            let horizontal = open(x - 1, y) && open(x + 1, y) &&
+                !open(x, y - 1) && !open(x, y + 1);
+            assert!(vertical || horizontal, "seed {} has a door at {:?} \
+                that isn't between a room and a hallway", seed, door);
+        }
+    }
+}
+
+#[test]
+fn test_generate_caves() {
+    let config = GenerationConfig {
+        width: 60,
+        height: 40,
+        algorithm: GenerationAlgorithm::Caves,
+        seed: 7,
+        edge_margin: 3,
+        repair_connectivity: false,
+        ..Default::default()
+    };
+    let floors = |world: &WorldMap| {
+        world.tiles().filter(|t| t.terrain == Terrain::Floor).count()
+    };
+    let (world, start) = WorldMap::generate(&config);
+    let regions = world.regions();
+    assert_eq!(regions.regions().len(), 1);
+    assert!(regions.label(start).is_some());
+    for tile in world.tiles() {
+        let loc = tile.loc;
+        if loc.x < 3 || loc.y < 3 || loc.x >= 57 || loc.y >= 37 {
+            assert!(tile.terrain == Terrain::Wall ||
+                tile.terrain == Terrain::Nothing);
+        }
+    }
+
+    // Joining keeps the smaller caverns that would otherwise be filled in.
+    let joined_config = GenerationConfig {
+        cave_join_regions: true,
+        ..config.clone()
+    };
+    let (joined, start) = WorldMap::generate(&joined_config);
+    assert_eq!(joined.regions().regions().len(), 1);
+    assert!(joined.regions().label(start).is_some());
+    assert!(floors(&joined) > floors(&world));
+
+    // Caves too full to smooth into anything still have a start.
+    let full = GenerationConfig {
+        cave_fill_percent: 100,
+        ..config
+    };
+    let (world, start) = WorldMap::generate(&full);
+    assert_eq!(world.get_tile(start).terrain, Terrain::Floor);
+}
+
+#[test]
+fn test_connect_regions() {
+    // Two rooms with nothing between them.
+    let mut world = WorldMap::empty(20, 10);
+    world.draw_feature(&Feature::room(3, 3).translate(2, 2));
+    world.draw_feature(&Feature::room(4, 2).translate(12, 5));
+    world.surround_floors_with_walls();
+    let start = Location::new(3, 3);
+    assert_eq!(world.regions().orphans(start).len(), 1);
+
+    let mut rng = GenerationConfig::default().rng();
+    assert_eq!(world.connect_regions(&mut rng, start), 1);
+    assert_eq!(world.regions().regions().len(), 1);
+    assert_eq!(world.connect_regions(&mut rng, start), 0);
+
+    // A room behind a locked door is reported apart from the unreachable
+    // ones, and isn't tunneled to.
+    let mut world = WorldMap::empty(20, 10);
+    world.draw_feature(&Feature::room(3, 3).translate(2, 2));
+    world.draw_feature(&Feature::room(3, 3).translate(6, 2));
+    world.surround_floors_with_walls();
+    world.get_tile_mut(Location::new(5, 3)).terrain = Terrain::DoorLocked;
+    let regions = world.regions();
+    assert_eq!(regions.regions().len(), 2);
+    assert!(regions.orphans(start).is_empty());
+    assert_eq!(regions.locked_away(start).len(), 1);
+    assert_eq!(world.connect_regions(&mut rng, start), 0);
+}
+
+#[test]
+fn test_add_loops() {
+    // A U-shaped hallway whose arms are separated by a single wall.
+    let mut world = WorldMap::empty(10, 14);
+    world.draw_feature(&Feature::hallway(10, false).translate(2, 2));
+    world.draw_feature(&Feature::hallway(3, true).translate(2, 11));
+    world.draw_feature(&Feature::hallway(10, false).translate(4, 2));
+    world.surround_floors_with_walls();
+    let (top_left, top_right) = (Location::new(2, 2), Location::new(4, 2));
+    let distance = |world: &WorldMap| {
+        Dijkstra::new(world, vec![top_left], 100).get(&top_right).unwrap()
+    };
+    assert_eq!(distance(&world), 18);
+
+    // Only passages near the top are worth making.
+    let mut rng = GenerationConfig::default().rng();
+    assert_eq!(world.add_loops(&mut rng, 1, 3, 1), 1);
+    assert!(distance(&world) < 18);
+    let passage: Vec<Location> = (0..world.height)
+        .map(|y| Location::new(3, y))
+        .filter(|l| world.get_tile(*l).terrain == Terrain::Floor)
+        .collect();
+    assert!(passage.len() == 2 && passage[0].y < 8);
 }